@@ -1,77 +1,193 @@
-use crate::util::parse_numbers;
-use anyhow::Result;
+use crate::util::parse_strings;
+use anyhow::{anyhow, bail, Result};
 
-fn q1(filename: &str) -> Result<u32> {
-    let base = 2;
-    let mut numbers = parse_numbers(filename, base)?;
-    let target = numbers.len() / 2;
+/// The readings of a report and its width, the length of the longest reading
+fn parse_report(filename: &str) -> Result<(Vec<usize>, u32)> {
+    let lines = parse_strings(filename)?;
+    let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u32;
+    let numbers = lines
+        .iter()
+        .map(|l| usize::from_str_radix(l, 2))
+        .collect::<Result<_, _>>()?;
+    Ok((numbers, width))
+}
+
+fn q1(filename: &str) -> Result<usize> {
+    let (numbers, width) = parse_report(filename)?;
     let mut gamma = 0;
     let mut epsilon = 0;
-    for digit in 0..12 {
-        let mut num_ones = 0;
-        for number in &numbers {
-            if number % 2 == 1 {
-                num_ones += 1;
-            }
-        }
-        if num_ones > target {
-            gamma += base.pow(digit);
+    for stats in column_stats(&numbers, width) {
+        if stats.ones > stats.zeros {
+            gamma |= 1 << stats.digit;
         } else {
-            epsilon += base.pow(digit);
+            epsilon |= 1 << stats.digit;
         }
-        numbers = numbers.iter().map(|n| n / 2).collect();
     }
     Ok(gamma * epsilon)
 }
 
+/// Bit counts for one column of a report. `digit` is the bit position, 0 being the least
+/// significant.
+#[derive(Debug, PartialEq)]
+struct ColumnStats {
+    digit: u32,
+    ones: usize,
+    zeros: usize,
+    /// Shannon entropy of the column in bits, 0 when every reading agrees.
+    entropy: f64,
+    tie: bool,
+}
+
+/// Stats for every column of the report, most significant bit first.
+fn column_stats(numbers: &[usize], width: u32) -> Vec<ColumnStats> {
+    (0..width)
+        .rev()
+        .map(|digit| {
+            let ones = numbers.iter().map(|n| nth_digit(*n, digit)).sum();
+            let zeros = numbers.len() - ones;
+            let entropy = [ones, zeros]
+                .iter()
+                .filter(|count| **count > 0)
+                .map(|count| {
+                    let p = *count as f64 / numbers.len() as f64;
+                    -p * p.log2()
+                })
+                .sum();
+            ColumnStats {
+                digit,
+                ones,
+                zeros,
+                entropy,
+                tie: ones == zeros,
+            }
+        })
+        .collect()
+}
+
 fn q2(filename: &str) -> Result<usize> {
-    let base = 2;
-    let numbers = parse_numbers(filename, base)?;
-    let o2 = find_report(numbers.clone(), true);
-    let co2 = find_report(numbers, false);
+    let (numbers, width) = parse_report(filename)?;
+    let trie = Trie::build(&numbers, width)?;
+    let o2 = trie
+        .find_report(true)
+        .ok_or_else(|| anyhow!("empty report"))?;
+    let co2 = trie
+        .find_report(false)
+        .ok_or_else(|| anyhow!("empty report"))?;
     Ok(o2 * co2)
 }
 
-fn find_report(mut numbers: Vec<usize>, o2: bool) -> usize {
-    let mut digit = 11;
-    while numbers.len() >= 2 {
-        let mut num_ones = 0;
-        for number in &numbers {
-            num_ones += nth_digit(*number, digit);
+#[derive(Debug, Default, Clone)]
+struct TrieNode {
+    count: usize,
+    children: [Option<usize>; 2],
+}
+
+/// Binary trie over fixed width readings, most significant bit first. Each node stores how
+/// many readings sit below it, so a rating is one walk from the root instead of a re-filter
+/// of the whole report per bit.
+#[derive(Debug)]
+struct Trie {
+    width: u32,
+    nodes: Vec<TrieNode>,
+}
+
+impl Trie {
+    /// Errors if a reading needs more than `width` bits
+    fn build(numbers: &[usize], width: u32) -> Result<Self> {
+        let mut nodes = vec![TrieNode::default()];
+        for number in numbers {
+            if width < usize::BITS && number >> width != 0 {
+                bail!("reading {:b} is wider than {} bits", number, width);
+            }
+            let mut node = 0;
+            nodes[node].count += 1;
+            for digit in (0..width).rev() {
+                let bit = nth_digit(*number, digit);
+                node = match nodes[node].children[bit] {
+                    Some(child) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children[bit] = Some(child);
+                        child
+                    }
+                };
+                nodes[node].count += 1;
+            }
         }
-        // This can probably be cleaned up a lot
-        let digit_to_keep = if o2 && numbers.len() % 2 == 0 && num_ones == numbers.len() / 2 {
-            // they are equal and we are o2
-            1
-        } else if numbers.len() % 2 == 0 && num_ones == numbers.len() / 2 {
-            // They are equal and we are c02
-            0
-        } else if o2 && num_ones >= numbers.len() / 2 {
-            // 1 is more common and we are o2
+        Ok(Self { width, nodes })
+    }
+
+    fn count(&self, child: Option<usize>) -> usize {
+        child.map_or(0, |c| self.nodes[c].count)
+    }
+
+    /// Which bit the bit criteria keeps at `node`. When a side is empty the other side is
+    /// kept, which also covers the single reading case.
+    fn digit_to_keep(&self, node: usize, o2: bool) -> usize {
+        let [zero, one] = self.nodes[node].children;
+        let zeros = self.count(zero);
+        let ones = self.count(one);
+        if zeros == 0 {
             1
-        } else if num_ones >= numbers.len() / 2 {
-            // 1 is more common and we are c02
+        } else if ones == 0 {
             0
         } else if o2 {
-            // 0 is more common and we are o2
-            0
+            // 1 wins ties for o2
+            (ones >= zeros) as usize
         } else {
-            // 0 is more common and we are c02
-            1
-        };
-        numbers = numbers
-            .iter()
-            .filter(|num| nth_digit(**num, digit) == digit_to_keep)
-            .cloned()
-            .collect();
-        digit -= 1;
+            // 0 wins ties for co2
+            (ones < zeros) as usize
+        }
+    }
+
+    /// The rating, `None` for an empty report
+    fn find_report(&self, o2: bool) -> Option<usize> {
+        self.walk(o2).map(|(value, _)| value)
     }
-    return numbers[0];
+
+    /// The steps the bit criteria takes while more than one reading is left, `None` for an
+    /// empty report
+    fn filter_trace(&self, o2: bool) -> Option<Vec<FilterStep>> {
+        self.walk(o2).map(|(_, trace)| trace)
+    }
+
+    fn walk(&self, o2: bool) -> Option<(usize, Vec<FilterStep>)> {
+        if self.nodes[0].count == 0 {
+            return None;
+        }
+        let mut node = 0;
+        let mut value = 0;
+        let mut trace = Vec::new();
+        for digit in (0..self.width).rev() {
+            let bit = self.digit_to_keep(node, o2);
+            let filtering = self.nodes[node].count >= 2;
+            value = value << 1 | bit;
+            // A non empty node always has a child on the side `digit_to_keep` picks
+            node = self.nodes[node].children[bit]?;
+            if filtering {
+                trace.push(FilterStep {
+                    digit,
+                    kept: bit,
+                    remaining: self.nodes[node].count,
+                });
+            }
+        }
+        Some((value, trace))
+    }
+}
+
+/// One round of the bit criteria: the bit position looked at, the value kept and how many
+/// readings survived.
+#[derive(Debug, PartialEq)]
+struct FilterStep {
+    digit: u32,
+    kept: usize,
+    remaining: usize,
 }
 
 fn nth_digit(number: usize, digit: u32) -> usize {
-    let two: u32 = 2;
-    (number as u32 / two.pow(digit) % 2) as usize
+    (number >> digit) & 1
 }
 
 #[cfg(test)]
@@ -82,4 +198,53 @@ mod tests {
         assert_eq!(q1("./data/day03.txt").unwrap(), 3429254);
         assert_eq!(q2("./data/day03.txt").unwrap(), 5410338);
     }
+
+    #[test]
+    fn test_trie() {
+        let numbers = [
+            0b00100, 0b11110, 0b10110, 0b10111, 0b10101, 0b01111, 0b00111, 0b11100, 0b10000,
+            0b11001, 0b00010, 0b01010,
+        ];
+        let trie = Trie::build(&numbers, 5).unwrap();
+        assert_eq!(trie.find_report(true), Some(23));
+        assert_eq!(trie.find_report(false), Some(10));
+
+        let empty = Trie::build(&[], 5).unwrap();
+        assert_eq!(empty.find_report(true), None);
+        assert_eq!(empty.filter_trace(false), None);
+        assert!(Trie::build(&numbers, 4).is_err());
+    }
+
+    #[test]
+    fn test_column_stats() {
+        let numbers = [
+            0b00100, 0b11110, 0b10110, 0b10111, 0b10101, 0b01111, 0b00111, 0b11100, 0b10000,
+            0b11001, 0b00010, 0b01010,
+        ];
+        let trace = Trie::build(&numbers, 5)
+            .unwrap()
+            .filter_trace(false)
+            .unwrap();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(
+            trace[2],
+            FilterStep {
+                digit: 2,
+                kept: 0,
+                remaining: 1
+            }
+        );
+
+        let stats = column_stats(&numbers, 5);
+        assert_eq!((stats[0].digit, stats[0].ones, stats[0].zeros), (4, 7, 5));
+        assert!(stats.iter().all(|s| !s.tie && s.entropy > 0.9));
+
+        // Bit 2 is split evenly, every reading has bit 1 set and none has bit 0
+        let stats = column_stats(&[0b110, 0b010, 0b110, 0b010], 3);
+        assert!(stats[0].tie);
+        assert_eq!(stats[0].entropy, 1.0);
+        assert!(!stats[1].tie);
+        assert_eq!((stats[1].ones, stats[1].entropy), (4, 0.0));
+        assert_eq!((stats[2].zeros, stats[2].entropy), (4, 0.0));
+    }
 }
@@ -1,27 +1,74 @@
 use crate::util::parse_strings;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use std::collections::HashMap;
 
-struct Bingo([[(usize, bool); 5]; 5]);
+/// The ways a board can win. Each rule expands to a set of lines, a board wins once every
+/// cell of one of those lines is marked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WinRule {
+    RowsAndColumns,
+    Diagonals,
+    FourCorners,
+    Blackout,
+}
+
+type Cell = (usize, usize);
+
+impl WinRule {
+    fn lines(&self, size: usize) -> Vec<Vec<Cell>> {
+        match self {
+            Self::RowsAndColumns => (0..size)
+                .map(|i| (0..size).map(|j| (i, j)).collect())
+                .chain((0..size).map(|i| (0..size).map(|j| (j, i)).collect()))
+                .collect(),
+            Self::Diagonals => vec![
+                (0..size).map(|i| (i, i)).collect(),
+                (0..size).map(|i| (i, size - 1 - i)).collect(),
+            ],
+            Self::FourCorners => {
+                let last = size - 1;
+                vec![vec![(0, 0), (0, last), (last, 0), (last, last)]]
+            }
+            Self::Blackout => vec![(0..size)
+                .flat_map(|i| (0..size).map(move |j| (i, j)))
+                .collect()],
+        }
+    }
+}
+
+/// Every line the given rules can win on
+fn winning_lines(rules: &[WinRule], size: usize) -> Vec<Vec<Cell>> {
+    rules.iter().flat_map(|rule| rule.lines(size)).collect()
+}
+
+#[derive(Debug, Clone)]
+struct Bingo(Vec<Vec<(usize, bool)>>);
 
 impl Bingo {
-    // Use the first 5 lines of this iterator to generate a bingo board
-    fn parse(iter: &mut dyn Iterator<Item = &String>) -> Self {
-        let mut board = [[(0, false); 5]; 5];
-        for (row_idx, row) in iter.take(5).enumerate() {
-            for (col_idx, val) in row
+    // Build a board out of its rows, every row needs as many numbers as there are rows
+    fn parse(rows: &[&String]) -> Result<Self> {
+        let mut board = Vec::new();
+        for row in rows {
+            let row = row
                 .split_whitespace()
-                .map(|x| x.parse::<usize>().unwrap())
-                .enumerate()
-            {
-                board[row_idx][col_idx] = (val, false);
-            }
+                .map(|x| Ok((x.parse::<usize>()?, false)))
+                .collect::<Result<Vec<_>>>()?;
+            board.push(row);
+        }
+        if board.iter().any(|row| row.len() != board.len()) {
+            bail!("bingo board is not square: {:?}", rows);
         }
-        Bingo(board)
+        Ok(Bingo(board))
+    }
+
+    fn size(&self) -> usize {
+        self.0.len()
     }
 
     fn mark(&mut self, value: usize) {
-        for row in &mut self.0[..] {
-            for (num, seen) in &mut row[..] {
+        for row in &mut self.0 {
+            for (num, seen) in row {
                 if *num == value {
                     *seen = true;
                 }
@@ -29,24 +76,19 @@ impl Bingo {
         }
     }
 
-    fn has_won(&self) -> bool {
-        for i in 0..5 {
-            let mut row = true;
-            let mut col = true;
-            for j in 0..5 {
-                row &= self.0[i][j].1;
-                col &= self.0[j][i].1;
-            }
-            if row || col {
-                return true;
-            }
-        }
-        false
+    fn is_complete(&self, line: &[Cell]) -> bool {
+        line.iter().all(|(r, c)| self.0[*r][*c].1)
+    }
+
+    fn has_won(&self, rules: &[WinRule]) -> bool {
+        winning_lines(rules, self.size())
+            .iter()
+            .any(|line| self.is_complete(line))
     }
 
     fn sum_unmarked(&self) -> usize {
         let mut sum = 0;
-        for row in self.0 {
+        for row in &self.0 {
             for (num, marked) in row {
                 if !marked {
                     sum += num;
@@ -57,52 +99,234 @@ impl Bingo {
     }
 }
 
-/// Parse out the call list and the boards available
+/// Parse out the call list and the boards available, boards are separated by blank lines
 fn parse(filename: &str) -> Result<(Vec<usize>, Vec<Bingo>)> {
     let data = parse_strings(filename)?;
     let mut iter = data.iter();
-    let call_line = iter.next().unwrap();
+    let call_line = match iter.next() {
+        Some(line) => line,
+        None => bail!("missing the call line"),
+    };
     let numbers = call_line
         .split(',')
-        .map(|x| x.parse::<usize>().unwrap())
-        .collect();
+        .map(|x| x.parse::<usize>())
+        .collect::<Result<_, _>>()?;
     let mut boards = Vec::new();
-    while iter.next().is_some() {
-        boards.push(Bingo::parse(&mut iter));
+    let mut rows = Vec::new();
+    for line in iter {
+        if line.trim().is_empty() {
+            if !rows.is_empty() {
+                boards.push(Bingo::parse(&rows)?);
+                rows.clear();
+            }
+        } else {
+            rows.push(line);
+        }
+    }
+    if !rows.is_empty() {
+        boards.push(Bingo::parse(&rows)?);
     }
     Ok((numbers, boards))
 }
 
-/// Find the winning board
-fn q1(filename: &str) -> Result<usize> {
-    let (numbers, mut boards) = parse(filename)?;
-    for number in numbers.into_iter() {
-        boards.iter_mut().for_each(|b| b.mark(number));
-        for board in &boards {
-            if board.has_won() {
-                return Ok(number * board.sum_unmarked());
+/// How a board finished: the turn (1 based count of calls) it won on, the number called, the
+/// line it completed and its score
+#[derive(Debug, Clone, PartialEq)]
+struct Win {
+    board: usize,
+    turn: usize,
+    call: usize,
+    line: Vec<Cell>,
+    score: usize,
+}
+
+/// The winning lines for one board size, along with the lines every cell belongs to
+struct Layout {
+    lines: Vec<Vec<Cell>>,
+    cell_lines: Vec<Vec<Vec<usize>>>,
+}
+
+impl Layout {
+    fn new(rules: &[WinRule], size: usize) -> Self {
+        let lines = winning_lines(rules, size);
+        let mut cell_lines = vec![vec![Vec::new(); size]; size];
+        for (idx, line) in lines.iter().enumerate() {
+            for (r, c) in line {
+                cell_lines[*r][*c].push(idx);
             }
         }
+        Self { lines, cell_lines }
     }
-
-    Ok(1)
 }
 
-/// Find the losing board
-fn q2(filename: &str) -> Result<usize> {
-    let (numbers, mut boards) = parse(filename)?;
-    for number in numbers.into_iter() {
-        boards.iter_mut().for_each(|b| b.mark(number));
-        if boards.len() == 1 {
-            if boards[0].has_won() {
-                return Ok(boards[0].sum_unmarked() * number);
+/// Play every call and record each board's win, in the order they won. Boards winning on the
+/// same turn are ordered by their index, boards that never win are left out.
+///
+/// Every number is indexed to the (board, row, column) cells holding it and each board keeps a
+/// hit count per winning line, so a call only touches the cells it marks.
+fn play(numbers: &[usize], mut boards: Vec<Bingo>, rules: &[WinRule]) -> Result<Vec<Win>> {
+    let mut layouts = HashMap::new();
+    let mut positions: HashMap<usize, Vec<(usize, usize, usize)>> = HashMap::new();
+    let mut hits = Vec::new();
+    let mut unmarked = Vec::new();
+    for (idx, board) in boards.iter().enumerate() {
+        let layout = layouts
+            .entry(board.size())
+            .or_insert_with(|| Layout::new(rules, board.size()));
+        hits.push(vec![0; layout.lines.len()]);
+        unmarked.push(board.sum_unmarked());
+        for (r, row) in board.0.iter().enumerate() {
+            for (c, (num, _)) in row.iter().enumerate() {
+                positions.entry(*num).or_default().push((idx, r, c));
             }
-        } else {
-            boards = boards.into_iter().filter(|x| !x.has_won()).collect();
         }
     }
 
-    Ok(1)
+    let mut won = vec![false; boards.len()];
+    let mut wins = Vec::new();
+    for (turn, number) in numbers.iter().enumerate() {
+        // The first line each board completed on this call
+        let mut completed: Vec<(usize, usize)> = Vec::new();
+        for (idx, r, c) in positions.get(number).into_iter().flatten() {
+            let (idx, r, c) = (*idx, *r, *c);
+            let cell = &mut boards[idx].0[r][c];
+            if won[idx] || cell.1 {
+                continue;
+            }
+            cell.1 = true;
+            unmarked[idx] -= number;
+            let layout = &layouts[&boards[idx].size()];
+            for line in &layout.cell_lines[r][c] {
+                hits[idx][*line] += 1;
+                if hits[idx][*line] == layout.lines[*line].len() {
+                    completed.push((idx, *line));
+                }
+            }
+        }
+        completed.sort_unstable();
+        completed.dedup_by_key(|(idx, _)| *idx);
+        for (idx, line) in completed {
+            won[idx] = true;
+            wins.push(Win {
+                board: idx,
+                turn: turn + 1,
+                call: *number,
+                line: layouts[&boards[idx].size()].lines[line].clone(),
+                score: unmarked[idx] * number,
+            });
+        }
+    }
+    if wins.is_empty() {
+        bail!("no board won after {} calls", numbers.len());
+    }
+    Ok(wins)
+}
+
+/// SplitMix64, enough randomness to shuffle call orders reproducibly from a seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// What the simulator found for one board. A win shared by several boards on the same turn
+/// counts as a fraction of a first place for each of them, `expected_turn` only averages the
+/// trials the board actually won in.
+#[derive(Debug, Clone, PartialEq)]
+struct Estimate {
+    board: usize,
+    first_win: f64,
+    expected_turn: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Tally {
+    first: f64,
+    turns: usize,
+    wins: usize,
+}
+
+/// Play `trials` games with the call order shuffled each time and estimate how likely every
+/// board is to win first and on which turn it wins. Trial `n` is seeded from `seed` and `n`
+/// so the estimates do not depend on how the trials get split across threads. It takes at
+/// least one trial to estimate anything.
+fn simulate(
+    numbers: &[usize],
+    boards: &[Bingo],
+    rules: &[WinRule],
+    trials: usize,
+    seed: u64,
+) -> Result<Vec<Estimate>> {
+    if trials == 0 {
+        bail!("simulating takes at least one trial");
+    }
+    let tallies = (0..trials)
+        .into_par_iter()
+        .map(|trial| {
+            let mut calls = numbers.to_vec();
+            SplitMix64(seed ^ (trial as u64).wrapping_mul(0xd1b54a32d192ed03)).shuffle(&mut calls);
+            let mut tally = vec![Tally::default(); boards.len()];
+            if let Ok(wins) = play(&calls, boards.to_vec(), rules) {
+                let first_turn = wins[0].turn;
+                let firsts = wins.iter().filter(|w| w.turn == first_turn).count();
+                for win in wins {
+                    let t = &mut tally[win.board];
+                    if win.turn == first_turn {
+                        t.first = 1.0 / firsts as f64;
+                    }
+                    t.turns = win.turn;
+                    t.wins = 1;
+                }
+            }
+            tally
+        })
+        .reduce(
+            || vec![Tally::default(); boards.len()],
+            |mut acc, tally| {
+                for (a, t) in acc.iter_mut().zip(tally) {
+                    a.first += t.first;
+                    a.turns += t.turns;
+                    a.wins += t.wins;
+                }
+                acc
+            },
+        );
+    Ok(tallies
+        .into_iter()
+        .enumerate()
+        .map(|(board, t)| Estimate {
+            board,
+            first_win: t.first / trials as f64,
+            expected_turn: (t.wins > 0).then(|| t.turns as f64 / t.wins as f64),
+        })
+        .collect())
+}
+
+/// Find the winning board
+fn q1(filename: &str) -> Result<usize> {
+    let (numbers, boards) = parse(filename)?;
+    let wins = play(&numbers, boards, &[WinRule::RowsAndColumns])?;
+    Ok(wins[0].score)
+}
+
+/// Find the losing board
+fn q2(filename: &str) -> Result<usize> {
+    let (numbers, boards) = parse(filename)?;
+    let wins = play(&numbers, boards, &[WinRule::RowsAndColumns])?;
+    Ok(wins[wins.len() - 1].score)
 }
 
 #[cfg(test)]
@@ -114,4 +338,54 @@ mod tests {
         assert_eq!(q1("./data/day04.txt").unwrap(), 49860);
         assert_eq!(q2("./data/day04.txt").unwrap(), 24628);
     }
+
+    #[test]
+    fn test_win_rules() {
+        let rows = ["1 2 3", "4 5 6", "7 8 9"].map(String::from);
+        let mut board = Bingo::parse(&rows.iter().collect::<Vec<_>>()).unwrap();
+        [1, 3, 7, 9].iter().for_each(|n| board.mark(*n));
+        assert!(board.has_won(&[WinRule::FourCorners]));
+        assert!(!board.has_won(&[WinRule::RowsAndColumns, WinRule::Diagonals]));
+        board.mark(5);
+        assert!(board.has_won(&[WinRule::Diagonals]));
+        assert!(!board.has_won(&[WinRule::Blackout]));
+
+        let rows = ["1 2 3", "4 5"].map(String::from);
+        assert!(Bingo::parse(&rows.iter().collect::<Vec<_>>()).is_err());
+    }
+
+    #[test]
+    fn test_play() {
+        let (numbers, boards) = parse("./data/day04.txt").unwrap();
+        let count = boards.len();
+        let wins = play(&numbers, boards.clone(), &[WinRule::RowsAndColumns]).unwrap();
+        assert_eq!(wins.len(), count);
+        assert!(wins.windows(2).all(|w| w[0].turn <= w[1].turn));
+        assert_eq!(wins[0].line.len(), 5);
+
+        for win in &wins {
+            let mut board = boards[win.board].clone();
+            numbers[..win.turn].iter().for_each(|n| board.mark(*n));
+            assert!(board.is_complete(&win.line));
+            assert_eq!(board.sum_unmarked() * win.call, win.score);
+        }
+
+        assert!(play(&numbers[..4], boards, &[WinRule::RowsAndColumns]).is_err());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let (numbers, boards) = parse("./data/day04.txt").unwrap();
+        let boards = &boards[..10];
+        let estimates = simulate(&numbers, boards, &[WinRule::RowsAndColumns], 200, 7).unwrap();
+        assert_eq!(estimates.len(), 10);
+        let total: f64 = estimates.iter().map(|e| e.first_win).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        let again = simulate(&numbers, boards, &[WinRule::RowsAndColumns], 200, 7).unwrap();
+        for (a, b) in estimates.iter().zip(again) {
+            assert!((a.first_win - b.first_win).abs() < 1e-9);
+            assert_eq!(a.expected_turn, b.expected_turn);
+        }
+        assert!(simulate(&numbers, boards, &[WinRule::RowsAndColumns], 0, 7).is_err());
+    }
 }
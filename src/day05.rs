@@ -1,6 +1,5 @@
 use crate::util::parse_strings;
 use anyhow::Result;
-use std::collections::HashSet;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 struct Point(usize, usize);
@@ -14,14 +13,27 @@ impl Point {
     }
 }
 
-fn gen_range_incl(start: usize, end: usize) -> Box<dyn Iterator<Item = usize>> {
-    if start <= end {
-        return Box::new(start..end + 1);
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
     } else {
-        return Box::new((end..start + 1).rev());
+        gcd(b, a % b)
     }
 }
 
+/// How a line runs across the map. `Other` covers every slope that is not a multiple of 45°.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Horizontal,
+    Vertical,
+    Diagonal,
+    Other,
+}
+
+const STRAIGHT: [LineKind; 2] = [LineKind::Horizontal, LineKind::Vertical];
+const STRAIGHT_AND_DIAGONAL: [LineKind; 3] =
+    [LineKind::Horizontal, LineKind::Vertical, LineKind::Diagonal];
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 struct Line {
     from: Point,
@@ -36,67 +48,364 @@ impl Line {
         Self { from, to }
     }
 
+    fn delta(&self) -> (isize, isize) {
+        (
+            self.to.0 as isize - self.from.0 as isize,
+            self.to.1 as isize - self.from.1 as isize,
+        )
+    }
+
+    fn kind(&self) -> LineKind {
+        let (dx, dy) = self.delta();
+        if dy == 0 {
+            LineKind::Horizontal
+        } else if dx == 0 {
+            LineKind::Vertical
+        } else if dx.abs() == dy.abs() {
+            LineKind::Diagonal
+        } else {
+            LineKind::Other
+        }
+    }
+
     fn is_straight(&self) -> bool {
-        self.from.0 == self.to.0 || self.from.1 == self.to.1
+        matches!(self.kind(), LineKind::Horizontal | LineKind::Vertical)
     }
 
-    fn points(&self, diags: bool) -> HashSet<Point> {
-        let mut points = HashSet::new();
-        if self.from.0 == self.to.0 {
-            for i in gen_range_incl(self.from.1, self.to.1) {
-                points.insert(Point(self.from.0, i));
-            }
-        } else if self.from.1 == self.to.1 {
-            for i in gen_range_incl(self.from.0, self.to.0) {
-                points.insert(Point(i, self.from.1));
-            }
-        } else if diags {
-            for (i, j) in
-                gen_range_incl(self.from.0, self.to.0).zip(gen_range_incl(self.from.1, self.to.1))
-            {
-                points.insert(Point(i, j));
-            }
+    /// Every lattice point on the segment, from `from` to `to`. The step is the delta divided
+    /// by the gcd of its components so no point is skipped whatever the slope.
+    fn points(&self) -> Vec<Point> {
+        let (dx, dy) = self.delta();
+        let steps = gcd(dx.unsigned_abs(), dy.unsigned_abs());
+        if steps == 0 {
+            return vec![self.from.clone()];
         }
-        points
+        let (sx, sy) = (dx / steps as isize, dy / steps as isize);
+        (0..=steps as isize)
+            .map(|i| {
+                Point(
+                    (self.from.0 as isize + i * sx) as usize,
+                    (self.from.1 as isize + i * sy) as usize,
+                )
+            })
+            .collect()
     }
 }
 
+/// The index of every line that is neither axis aligned nor a 45° diagonal
+fn irregular_lines(lines: &[Line]) -> Vec<usize> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.kind() == LineKind::Other)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
 fn parse(filename: &str) -> Result<Vec<Line>> {
     let strings = parse_strings(filename)?;
-    Ok(strings.iter().map(|x| Line::from_str(&*x)).collect())
+    Ok(strings.iter().map(|x| Line::from_str(x)).collect())
 }
 
-fn find_intersection(filename: &str, with_diags: bool) -> Result<usize> {
-    let lines = parse(filename)?;
-    let mut double_points: HashSet<Point> = HashSet::new();
-    for i in 0..lines.len() - 1 {
-        for j in i + 1..lines.len() {
-            let intersection = lines[i]
-                .points(with_diags)
-                .intersection(&lines[j].points(with_diags))
-                .cloned()
+/// How many lines cover each point, kept as a dense grid over the bounding box of the lines
+#[derive(Debug)]
+struct Coverage {
+    width: usize,
+    height: usize,
+    counts: Vec<usize>,
+}
+
+impl Coverage {
+    /// Only lines of the given kinds are drawn
+    fn build(lines: &[Line], kinds: &[LineKind]) -> Self {
+        let width = lines
+            .iter()
+            .map(|l| l.from.0.max(l.to.0) + 1)
+            .max()
+            .unwrap_or(0);
+        let height = lines
+            .iter()
+            .map(|l| l.from.1.max(l.to.1) + 1)
+            .max()
+            .unwrap_or(0);
+        let mut counts = vec![0; width * height];
+        for line in lines.iter().filter(|l| kinds.contains(&l.kind())) {
+            for Point(x, y) in line.points() {
+                counts[y * width + x] += 1;
+            }
+        }
+        Self {
+            width,
+            height,
+            counts,
+        }
+    }
+
+    fn index(&self, point: &Point) -> Option<usize> {
+        if point.0 < self.width && point.1 < self.height {
+            Some(point.1 * self.width + point.0)
+        } else {
+            None
+        }
+    }
+
+    fn count_at_least(&self, k: usize) -> usize {
+        self.counts.iter().filter(|c| **c >= k).count()
+    }
+
+    fn max(&self) -> usize {
+        self.counts.iter().cloned().max().unwrap_or(0)
+    }
+
+    /// Every point covered by at least `threshold` lines with its count, row by row
+    fn hot_spots(&self, threshold: usize) -> Vec<(Point, usize)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count >= threshold)
+            .map(|(idx, count)| (Point(idx % self.width, idx / self.width), *count))
+            .collect()
+    }
+
+    fn hot_spots_csv(&self, threshold: usize) -> String {
+        let mut csv = String::from("x,y,count\n");
+        for (Point(x, y), count) in self.hot_spots(threshold) {
+            csv += &format!("{},{},{}\n", x, y, count);
+        }
+        csv
+    }
+
+    fn write_hot_spots_csv(&self, filename: &str, threshold: usize) -> Result<()> {
+        std::fs::write(filename, self.hot_spots_csv(threshold))?;
+        Ok(())
+    }
+
+    /// Plain (P2) greyscale image, the most covered point is white
+    fn pgm(&self) -> String {
+        let mut image = format!(
+            "P2\n{} {}\n{}\n",
+            self.width,
+            self.height,
+            self.max().max(1)
+        );
+        for row in self.counts.chunks(self.width.max(1)) {
+            let row: Vec<String> = row.iter().map(usize::to_string).collect();
+            image += &row.join(" ");
+            image.push('\n');
+        }
+        image
+    }
+
+    fn write_pgm(&self, filename: &str) -> Result<()> {
+        std::fs::write(filename, self.pgm())?;
+        Ok(())
+    }
+
+    /// Plain (P3) colour image going from black through red and yellow to white as the
+    /// coverage grows
+    fn ppm(&self) -> String {
+        let max = self.max().max(1) as f64;
+        let channel = |t: f64| (t.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let mut image = format!("P3\n{} {}\n255\n", self.width, self.height);
+        for row in self.counts.chunks(self.width.max(1)) {
+            let row: Vec<String> = row
+                .iter()
+                .map(|count| {
+                    let t = 3.0 * *count as f64 / max;
+                    format!("{} {} {}", channel(t), channel(t - 1.0), channel(t - 2.0))
+                })
                 .collect();
-            double_points = double_points.union(&intersection).cloned().collect();
+            image += &row.join(" ");
+            image.push('\n');
         }
+        image
     }
-    Ok(double_points.len())
+
+    fn write_ppm(&self, filename: &str) -> Result<()> {
+        std::fs::write(filename, self.ppm())?;
+        Ok(())
+    }
+}
+
+/// Lines indexed by the points they cover, built once and then queried. The line ids of each
+/// grid point are stored back to back, `offsets[i]..offsets[i + 1]` is the slice for point `i`.
+#[derive(Debug)]
+struct VentIndex {
+    lines: Vec<Line>,
+    coverage: Coverage,
+    offsets: Vec<usize>,
+    ids: Vec<usize>,
+}
+
+impl VentIndex {
+    fn build(lines: Vec<Line>) -> Self {
+        let all = [
+            LineKind::Horizontal,
+            LineKind::Vertical,
+            LineKind::Diagonal,
+            LineKind::Other,
+        ];
+        let coverage = Coverage::build(&lines, &all);
+        let mut offsets = vec![0; coverage.counts.len() + 1];
+        for (idx, count) in coverage.counts.iter().enumerate() {
+            offsets[idx + 1] = offsets[idx] + count;
+        }
+        let mut next = offsets.clone();
+        let mut ids = vec![0; offsets[coverage.counts.len()]];
+        for (id, line) in lines.iter().enumerate() {
+            for point in line.points() {
+                let cell = coverage.index(&point).unwrap();
+                ids[next[cell]] = id;
+                next[cell] += 1;
+            }
+        }
+        Self {
+            lines,
+            coverage,
+            offsets,
+            ids,
+        }
+    }
+
+    /// Ids of the lines passing through `point`, in input order
+    fn lines_through(&self, point: &Point) -> &[usize] {
+        match self.coverage.index(point) {
+            Some(cell) => &self.ids[self.offsets[cell]..self.offsets[cell + 1]],
+            None => &[],
+        }
+    }
+
+    /// Ids of the other lines sharing at least one point with line `id`
+    fn crossing(&self, id: usize) -> Vec<usize> {
+        let mut result: Vec<usize> = self.lines[id]
+            .points()
+            .iter()
+            .flat_map(|p| self.lines_through(p))
+            .filter(|other| **other != id)
+            .cloned()
+            .collect();
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    /// The largest 4-connected group of points each covered by at least `k` lines
+    fn largest_cluster(&self, k: usize) -> Vec<Point> {
+        let Coverage {
+            width,
+            height,
+            counts,
+        } = &self.coverage;
+        let mut seen = vec![false; counts.len()];
+        let mut best = Vec::new();
+        for start in 0..counts.len() {
+            if seen[start] || counts[start] < k {
+                continue;
+            }
+            seen[start] = true;
+            let mut cluster = Vec::new();
+            let mut to_check = vec![start];
+            while let Some(cell) = to_check.pop() {
+                let (x, y) = (cell % width, cell / width);
+                cluster.push(Point(x, y));
+                let mut neighbors = Vec::new();
+                if x > 0 {
+                    neighbors.push(cell - 1);
+                }
+                if x + 1 < *width {
+                    neighbors.push(cell + 1);
+                }
+                if y > 0 {
+                    neighbors.push(cell - width);
+                }
+                if y + 1 < *height {
+                    neighbors.push(cell + width);
+                }
+                for n in neighbors {
+                    if !seen[n] && counts[n] >= k {
+                        seen[n] = true;
+                        to_check.push(n);
+                    }
+                }
+            }
+            if cluster.len() > best.len() {
+                best = cluster;
+            }
+        }
+        best
+    }
+}
+
+fn find_intersection(filename: &str, kinds: &[LineKind]) -> Result<usize> {
+    let lines = parse(filename)?;
+    Ok(Coverage::build(&lines, kinds).count_at_least(2))
 }
 
 fn q1(filename: &str) -> Result<usize> {
-    find_intersection(filename, false)
+    find_intersection(filename, &STRAIGHT)
 }
 
 fn q2(filename: &str) -> Result<usize> {
-    find_intersection(filename, true)
+    find_intersection(filename, &STRAIGHT_AND_DIAGONAL)
 }
 
 #[cfg(test)]
 mod tests {
-    //use super::*;
+    use super::*;
 
     #[test]
     fn test() {
-        //assert_eq!(q1("./data/day05.txt").unwrap(), 5632);
-        //assert_eq!(q2("./data/day05.txt").unwrap(), 22213);
+        assert_eq!(q1("./data/day05.txt").unwrap(), 5632);
+        assert_eq!(q2("./data/day05.txt").unwrap(), 22213);
+    }
+
+    #[test]
+    fn test_any_slope() {
+        let lines: Vec<Line> = ["0,0 -> 6,4", "6,3 -> 0,0", "2,2 -> 2,2", "5,5 -> 8,2"]
+            .iter()
+            .map(|s| Line::from_str(s))
+            .collect();
+        assert_eq!(
+            lines[0].points(),
+            vec![Point(0, 0), Point(3, 2), Point(6, 4)]
+        );
+        assert_eq!(lines[1].points().len(), 4);
+        assert_eq!(lines[2].points(), vec![Point(2, 2)]);
+        assert_eq!(irregular_lines(&lines), vec![0, 1]);
+        assert_eq!(lines[3].kind(), LineKind::Diagonal);
+    }
+
+    #[test]
+    fn test_exports() {
+        let lines: Vec<Line> = ["0,0 -> 2,0", "1,0 -> 1,1"]
+            .iter()
+            .map(|s| Line::from_str(s))
+            .collect();
+        let coverage = Coverage::build(&lines, &STRAIGHT);
+        assert_eq!(coverage.hot_spots(2), vec![(Point(1, 0), 2)]);
+
+        assert_eq!(
+            coverage.hot_spots_csv(1),
+            "x,y,count\n0,0,1\n1,0,2\n2,0,1\n1,1,1\n"
+        );
+        assert_eq!(coverage.pgm(), "P2\n3 2\n2\n1 2 1\n0 1 0\n");
+        assert!(coverage
+            .ppm()
+            .starts_with("P3\n3 2\n255\n255 128 0 255 255 255 255 128 0\n"));
+    }
+
+    #[test]
+    fn test_index() {
+        let lines: Vec<Line> = ["0,0 -> 4,0", "2,0 -> 2,2", "0,2 -> 2,0", "4,4 -> 4,3"]
+            .iter()
+            .map(|s| Line::from_str(s))
+            .collect();
+        let index = VentIndex::build(lines);
+        assert_eq!(index.lines_through(&Point(2, 0)), &[0, 1, 2]);
+        assert_eq!(index.lines_through(&Point(9, 9)), &[] as &[usize]);
+        assert_eq!(index.crossing(1), vec![0, 2]);
+        assert!(index.crossing(3).is_empty());
+        assert_eq!(index.largest_cluster(2), vec![Point(2, 0)]);
+        assert_eq!(index.largest_cluster(1).len(), 8);
     }
 }
@@ -1,23 +1,291 @@
 use crate::util::parse_numbers_comma;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-fn num_lanternfish(filename: &str, days: usize) -> Result<usize> {
-    let numbers = parse_numbers_comma(filename)?;
-    let mut counts = [0; 9];
-    for n in numbers {
-        counts[n as usize] += 1;
+/// The lifecycle of one kind of fish. After spawning a fish waits `cycle` days before it
+/// spawns again, a newborn waits `newborn_delay` days on top of that for its first spawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Species {
+    cycle: usize,
+    newborn_delay: usize,
+}
+
+const LANTERNFISH: Species = Species {
+    cycle: 7,
+    newborn_delay: 2,
+};
+
+impl Species {
+    /// Number of distinct timer values, a newborn starts at the highest one
+    fn slots(&self) -> usize {
+        self.cycle + self.newborn_delay
+    }
+}
+
+/// Fish of a single species, counted per timer value
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct School {
+    species: Species,
+    counts: Vec<usize>,
+}
+
+impl School {
+    fn from_timers(species: Species, timers: &[isize]) -> Result<Self> {
+        if species.cycle == 0 {
+            bail!("a species needs a cycle of at least one day");
+        }
+        let mut counts = vec![0; species.slots()];
+        for timer in timers {
+            if *timer < 0 || *timer as usize >= counts.len() {
+                bail!("timer {} is outside 0..{}", timer, counts.len());
+            }
+            counts[*timer as usize] += 1;
+        }
+        Ok(Self { species, counts })
+    }
+
+    fn step(&mut self) {
+        let spawning = self.counts[0];
+        self.counts.rotate_left(1);
+        self.counts[self.species.cycle - 1] += spawning;
+    }
+
+    /// Like `step`, `None` if a count overflows
+    fn checked_step(&mut self) -> Option<()> {
+        let spawning = self.counts[0];
+        self.counts.rotate_left(1);
+        let parents = &mut self.counts[self.species.cycle - 1];
+        *parents = parents.checked_add(spawning)?;
+        Some(())
+    }
+
+    fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    fn checked_total(&self) -> Option<usize> {
+        self.counts
+            .iter()
+            .try_fold(0usize, |sum, c| sum.checked_add(*c))
+    }
+
+    /// The count in every timer bucket for day 0 (the starting school) up to `days`
+    fn history(&self, days: usize) -> Vec<Vec<usize>> {
+        let mut school = self.clone();
+        let mut history = vec![school.counts.clone()];
+        for _day in 0..days {
+            school.step();
+            history.push(school.counts.clone());
+        }
+        history
     }
 
+    /// The first day the population is above `threshold`, `None` for an empty school or when
+    /// the population overflows a `usize` before getting there
+    fn first_day_above(&self, threshold: usize) -> Option<usize> {
+        let mut school = self.clone();
+        let mut day = 0;
+        loop {
+            match school.checked_total()? {
+                0 => return None,
+                total if total > threshold => return Some(day),
+                _ => {}
+            }
+            school.checked_step()?;
+            day += 1;
+        }
+    }
+}
+
+/// A history as `day,total,t0,t1,..` csv rows, one per day
+fn history_csv(history: &[Vec<usize>]) -> String {
+    let slots = history.first().map_or(0, Vec::len);
+    let mut csv = String::from("day,total");
+    (0..slots).for_each(|t| csv += &format!(",t{}", t));
+    csv.push('\n');
+    for (day, counts) in history.iter().enumerate() {
+        csv += &format!("{},{}", day, counts.iter().sum::<usize>());
+        counts.iter().for_each(|c| csv += &format!(",{}", c));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn write_history_csv(history: &[Vec<usize>], filename: &str) -> Result<()> {
+    std::fs::write(filename, history_csv(history))?;
+    Ok(())
+}
+
+/// Total population of several independent schools after `days`
+fn population(schools: &mut [School], days: usize) -> usize {
     for _day in 0..days {
-        let zero_count = counts[0];
-        for i in 0..8 {
-            counts[i] = counts[i + 1]
+        schools.iter_mut().for_each(School::step);
+    }
+    schools.iter().map(School::total).sum()
+}
+
+/// What a population can be counted in when working with the transition matrix
+trait Count: Clone {
+    fn from_usize(n: usize) -> Self;
+    fn add(&self, other: &Self) -> Self;
+    fn mul(&self, other: &Self) -> Self;
+}
+
+/// Counts modulo the prime `P`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ModP<const P: u64>(u64);
+
+impl<const P: u64> Count for ModP<P> {
+    fn from_usize(n: usize) -> Self {
+        Self(n as u64 % P)
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self(((self.0 as u128 + other.0 as u128) % P as u128) as u64)
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        Self(((self.0 as u128 * other.0 as u128) % P as u128) as u64)
+    }
+}
+
+/// Arbitrary precision unsigned integer, little endian base 2^32 limbs without trailing zeros
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Big(Vec<u32>);
+
+impl Big {
+    fn trim(mut self) -> Self {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+}
+
+impl Count for Big {
+    fn from_usize(n: usize) -> Self {
+        let n = n as u64;
+        Self(vec![n as u32, (n >> 32) as u32]).trim()
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut limbs = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let sum = *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64;
+            let sum = sum + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
         }
-        counts[8] = zero_count;
-        counts[6] += zero_count;
+        limbs.push(carry as u32);
+        Self(limbs).trim()
     }
 
-    Ok(counts.iter().sum())
+    fn mul(&self, other: &Self) -> Self {
+        let mut limbs = vec![0u32; self.0.len() + other.0.len()];
+        for (i, a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, b) in other.0.iter().enumerate() {
+                let cur = limbs[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            limbs[i + other.0.len()] = carry as u32;
+        }
+        Self(limbs).trim()
+    }
+}
+
+impl std::fmt::Display for Big {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Peel off base 10^9 chunks, least significant first
+        let mut limbs = self.0.clone();
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let mut rem = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let cur = (rem << 32) | *limb as u64;
+                *limb = (cur / 1_000_000_000) as u32;
+                rem = cur % 1_000_000_000;
+            }
+            chunks.push(rem);
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+        match chunks.pop() {
+            None => write!(f, "0"),
+            Some(first) => {
+                write!(f, "{}", first)?;
+                chunks.iter().rev().try_for_each(|c| write!(f, "{:09}", c))
+            }
+        }
+    }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+fn mat_mul<T: Count>(a: &Matrix<T>, b: &Matrix<T>) -> Matrix<T> {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    (1..n).fold(a[i][0].mul(&b[0][j]), |acc, k| {
+                        acc.add(&a[i][k].mul(&b[k][j]))
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl Species {
+    /// The one day transition, `new[i] = sum(m[i][j] * old[j])`
+    fn transition<T: Count>(&self) -> Matrix<T> {
+        let n = self.slots();
+        let mut m = vec![vec![0; n]; n];
+        for i in 0..n - 1 {
+            m[i][i + 1] = 1;
+        }
+        m[n - 1][0] += 1;
+        m[self.cycle - 1][0] += 1;
+        m.into_iter()
+            .map(|row| row.into_iter().map(T::from_usize).collect())
+            .collect()
+    }
+}
+
+impl School {
+    /// Population after `days` by raising the transition matrix to that power with repeated
+    /// squaring, so it takes O(log days) matrix products. Exact `Big` counts still grow by a
+    /// few bits a day, for very large day counts count modulo a prime with `ModP` instead.
+    fn population_after<T: Count>(&self, mut days: u64) -> T {
+        let n = self.counts.len();
+        let mut base = self.species.transition::<T>();
+        let mut power: Matrix<T> = (0..n)
+            .map(|i| (0..n).map(|j| T::from_usize((i == j) as usize)).collect())
+            .collect();
+        while days > 0 {
+            if days & 1 == 1 {
+                power = mat_mul(&power, &base);
+            }
+            days >>= 1;
+            if days > 0 {
+                base = mat_mul(&base, &base);
+            }
+        }
+        let counts: Vec<T> = self.counts.iter().cloned().map(T::from_usize).collect();
+        power
+            .iter()
+            .flat_map(|row| row.iter().zip(&counts).map(|(m, c)| m.mul(c)))
+            .fold(T::from_usize(0), |acc, x| acc.add(&x))
+    }
+}
+
+fn num_lanternfish(filename: &str, days: usize) -> Result<usize> {
+    let numbers = parse_numbers_comma(filename)?;
+    let school = School::from_timers(LANTERNFISH, &numbers)?;
+    Ok(population(&mut [school], days))
 }
 
 fn q1(filename: &str) -> Result<usize> {
@@ -37,4 +305,87 @@ mod tests {
         assert_eq!(q1("./data/day06.txt").unwrap(), 395627);
         assert_eq!(q2("./data/day06.txt").unwrap(), 1767323539209);
     }
+
+    #[test]
+    fn test_species() {
+        let fish = School::from_timers(LANTERNFISH, &[3, 4, 3, 1, 2]).unwrap();
+        assert_eq!(population(&mut [fish.clone()], 18), 26);
+
+        // A species that spawns every 2 days with no extra delay doubles every 2 days
+        let rabbits = Species {
+            cycle: 2,
+            newborn_delay: 0,
+        };
+        let rabbit = School::from_timers(rabbits, &[0]).unwrap();
+        assert_eq!(population(&mut [fish, rabbit], 18), 26 + 512);
+        assert!(School::from_timers(rabbits, &[2]).is_err());
+        let stuck = Species {
+            cycle: 0,
+            newborn_delay: 1,
+        };
+        assert!(School::from_timers(stuck, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_history() {
+        let school = School::from_timers(LANTERNFISH, &[3, 4, 3, 1, 2]).unwrap();
+        let history = school.history(18);
+        assert_eq!(history.len(), 19);
+        assert_eq!(history[1], vec![1, 1, 2, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(history[18].iter().sum::<usize>(), 26);
+        assert_eq!(school.first_day_above(25), Some(18));
+        assert_eq!(school.first_day_above(5), Some(2));
+        let lone = School::from_timers(LANTERNFISH, &[3]).unwrap();
+        assert_eq!(lone.first_day_above(usize::MAX), None);
+        assert!(lone.first_day_above(usize::MAX / 2).is_some());
+
+        assert_eq!(
+            history_csv(&history[..2]),
+            "day,total,t0,t1,t2,t3,t4,t5,t6,t7,t8\n0,5,0,1,1,2,1,0,0,0,0\n1,5,1,1,2,1,0,0,0,0,0\n"
+        );
+    }
+
+    fn rem(big: &Big, p: u64) -> u64 {
+        big.0
+            .iter()
+            .rev()
+            .fold(0, |rem, limb| ((rem << 32) | *limb as u64) % p)
+    }
+
+    #[test]
+    fn test_matrix() {
+        let numbers = parse_numbers_comma("./data/day06.txt").unwrap();
+        let school = School::from_timers(LANTERNFISH, &numbers).unwrap();
+        assert_eq!(
+            school.population_after::<Big>(256).to_string(),
+            "1767323539209"
+        );
+        assert_eq!(
+            school.population_after::<ModP<1_000_000_007>>(256),
+            ModP(1767323539209 % 1_000_000_007)
+        );
+
+        // Step day by day in `Big` to check the matrix power well past where usize overflows,
+        // and the modular power against the exact count reduced mod P
+        let mut counts: Vec<Big> = school.counts.iter().map(|c| Big::from_usize(*c)).collect();
+        for day in 1..=3000 {
+            let spawning = counts[0].clone();
+            counts.rotate_left(1);
+            counts[6] = counts[6].add(&spawning);
+            if day % 1000 == 0 {
+                let total = counts.iter().fold(Big::from_usize(0), |acc, c| acc.add(c));
+                assert_eq!(school.population_after::<Big>(day), total);
+                assert_eq!(
+                    school.population_after::<ModP<998_244_353>>(day),
+                    ModP(rem(&total, 998_244_353))
+                );
+            }
+        }
+
+        let two_32 = Big::from_usize(1 << 32);
+        assert_eq!(
+            two_32.mul(&two_32).mul(&two_32).to_string(),
+            "79228162514264337593543950336"
+        );
+    }
 }
@@ -1,27 +1,187 @@
 use crate::util::parse_numbers_comma;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
-fn q1(filename: &str) -> Result<isize> {
-    let mut numbers = parse_numbers_comma(filename)?;
-    numbers.sort();
-    let median = numbers[numbers.len() / 2];
-    let result = numbers.iter().map(|x| (*x - median).abs()).sum();
-    Ok(result)
+/// Where the crabs line up and the total fuel it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Alignment {
+    position: isize,
+    cost: isize,
 }
 
-fn q2(filename: &str) -> Result<isize> {
-    let distance = |from: isize, to: isize| {
-        let n = (from - to).abs();
-        (n * (n + 1)) / 2
+fn linear(from: isize, to: isize) -> isize {
+    (from - to).abs()
+}
+
+fn triangular(from: isize, to: isize) -> isize {
+    let n = (from - to).abs();
+    (n * (n + 1)) / 2
+}
+
+/// Find the cheapest target for `cost(crab, target)`, which has to be convex in the target.
+/// The total is then convex too, so a binary search for the first target where moving one
+/// further stops paying off replaces scanning every target. `None` when there are no crabs.
+fn align<F>(positions: &[isize], cost: F) -> Option<Alignment>
+where
+    F: Fn(isize, isize) -> isize,
+{
+    let crabs: Vec<(isize, isize)> = positions.iter().map(|x| (*x, 1)).collect();
+    align_weighted(&crabs, cost)
+}
+
+/// Same as `align` for `(position, weight)` crabs, each crab's cost is multiplied by its
+/// weight. Weights have to be non negative to keep the total convex.
+fn align_weighted<F>(crabs: &[(isize, isize)], cost: F) -> Option<Alignment>
+where
+    F: Fn(isize, isize) -> isize,
+{
+    let total = |target: isize| {
+        crabs
+            .iter()
+            .map(|(x, weight)| cost(*x, target) * weight)
+            .sum::<isize>()
     };
+    let mut low = crabs.iter().map(|(x, _)| *x).min()?;
+    let mut high = crabs.iter().map(|(x, _)| *x).max()?;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if total(mid) <= total(mid + 1) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Some(Alignment {
+        position: low,
+        cost: total(low),
+    })
+}
+
+/// A crab with any number of coordinates and a fuel multiplier
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Crab {
+    position: Vec<isize>,
+    weight: isize,
+}
+
+/// Crabs are separated by commas or whitespace, each one is `x[:weight]` where `x` is one or
+/// more coordinates joined by `;` and `weight` is not negative. A plain comma separated list is
+/// read as 1D crabs of weight 1.
+fn crabs_from_str(s: &str) -> Result<Vec<Crab>> {
+    let crabs = s
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut split = entry.splitn(2, ':');
+            let position = split
+                .next()
+                .unwrap()
+                .split(';')
+                .map(|x| x.parse::<isize>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let weight = match split.next() {
+                Some(w) => w.parse::<isize>()?,
+                None => 1,
+            };
+            if weight < 0 {
+                bail!("crab {:?} has a negative weight", entry);
+            }
+            Ok(Crab { position, weight })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(first) = crabs.first() {
+        if let Some(crab) = crabs
+            .iter()
+            .find(|c| c.position.len() != first.position.len())
+        {
+            bail!(
+                "crab {:?} does not have {} coordinates",
+                crab,
+                first.position.len()
+            );
+        }
+    }
+    Ok(crabs)
+}
+
+fn parse_crabs(filename: &str) -> Result<Vec<Crab>> {
+    crabs_from_str(&std::fs::read_to_string(filename)?)
+}
+
+/// Best meeting point for crabs in any number of dimensions when the fuel is a sum of
+/// `cost(crab, target)` over the axes, `cost` being convex per axis like `linear` (Manhattan)
+/// or `triangular` (triangular per axis). Each axis is then aligned on its own. Costs of the
+/// whole distance, such as triangular of the Manhattan distance, need `align_manhattan`.
+fn align_axes<F>(crabs: &[Crab], cost: F) -> Option<(Vec<isize>, isize)>
+where
+    F: Fn(isize, isize) -> isize + Copy,
+{
+    let dims = crabs.first()?.position.len();
+    let mut position = Vec::with_capacity(dims);
+    let mut total = 0;
+    for axis in 0..dims {
+        let axis_crabs: Vec<(isize, isize)> =
+            crabs.iter().map(|c| (c.position[axis], c.weight)).collect();
+        let alignment = align_weighted(&axis_crabs, cost)?;
+        position.push(alignment.position);
+        total += alignment.cost;
+    }
+    Some((position, total))
+}
+
+/// Best meeting point when the fuel is `cost(0, d)` for the Manhattan distance `d` to the
+/// target, `cost` being convex and growing in `d` like `linear` or `triangular`. That does not
+/// split by axis but the total is still convex in every coordinate, so each axis is binary
+/// searched with the best over the axes after it as the cost of a coordinate.
+fn align_manhattan<F>(crabs: &[Crab], cost: F) -> Option<(Vec<isize>, isize)>
+where
+    F: Fn(isize, isize) -> isize,
+{
+    let mut target = vec![0; crabs.first()?.position.len()];
+    let total = search_axis(crabs, &cost, &mut target, 0);
+    Some((target, total))
+}
+
+/// The lowest total over coordinates `axis..` of `target`, which is left set to where it is
+fn search_axis<F>(crabs: &[Crab], cost: &F, target: &mut [isize], axis: usize) -> isize
+where
+    F: Fn(isize, isize) -> isize,
+{
+    if axis == target.len() {
+        return crabs
+            .iter()
+            .map(|c| {
+                let distance = c.position.iter().zip(&*target).map(|(x, t)| (x - t).abs());
+                cost(0, distance.sum()) * c.weight
+            })
+            .sum();
+    }
+    let best_at = |coordinate, target: &mut [isize]| {
+        target[axis] = coordinate;
+        search_axis(crabs, cost, target, axis + 1)
+    };
+    let mut low = crabs.iter().map(|c| c.position[axis]).min().unwrap_or(0);
+    let mut high = crabs.iter().map(|c| c.position[axis]).max().unwrap_or(0);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if best_at(mid, target) <= best_at(mid + 1, target) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    best_at(low, target)
+}
+
+fn q1(filename: &str) -> Result<isize> {
     let numbers = parse_numbers_comma(filename)?;
-    let max = *(numbers.iter().max().unwrap()) + 1;
-    let min = *(numbers.iter().min().unwrap());
-    let value = (min..max)
-        .map(|target| numbers.iter().map(|x| distance(*x, target)).sum::<isize>())
-        .min();
+    let alignment = align(&numbers, linear).ok_or_else(|| anyhow!("no crabs"))?;
+    Ok(alignment.cost)
+}
 
-    Ok(value.unwrap())
+fn q2(filename: &str) -> Result<isize> {
+    let numbers = parse_numbers_comma(filename)?;
+    let alignment = align(&numbers, triangular).ok_or_else(|| anyhow!("no crabs"))?;
+    Ok(alignment.cost)
 }
 
 #[cfg(test)]
@@ -33,4 +193,70 @@ mod tests {
         assert_eq!(q1("./data/day07.txt").unwrap(), 326132);
         assert_eq!(q2("./data/day07.txt").unwrap(), 88612508);
     }
+
+    #[test]
+    fn test_align() {
+        let crabs = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+        assert_eq!(
+            align(&crabs, linear),
+            Some(Alignment {
+                position: 2,
+                cost: 37
+            })
+        );
+        assert_eq!(
+            align(&crabs, triangular),
+            Some(Alignment {
+                position: 5,
+                cost: 168
+            })
+        );
+        let squared = |from: isize, to: isize| (from - to).pow(2);
+        assert_eq!(align(&crabs, squared).unwrap().position, 5);
+        assert_eq!(align(&[], linear), None);
+    }
+
+    #[test]
+    fn test_crabs() {
+        let crabs = parse_crabs("./data/day07.txt").unwrap();
+        let numbers = parse_numbers_comma("./data/day07.txt").unwrap();
+        assert!(crabs.iter().all(|c| c.weight == 1));
+        assert_eq!(
+            crabs.iter().map(|c| c.position[0]).collect::<Vec<_>>(),
+            numbers
+        );
+        assert_eq!(align_axes(&crabs, triangular).unwrap().1, 88612508);
+        assert_eq!(align_manhattan(&crabs, triangular).unwrap().1, 88612508);
+
+        let crabs = crabs_from_str("0;0:3, 10;4\n2;10\n").unwrap();
+        assert_eq!(
+            crabs[0],
+            Crab {
+                position: vec![0, 0],
+                weight: 3
+            }
+        );
+        assert_eq!(align_axes(&crabs, linear), Some((vec![0, 0], 12 + 14)));
+        assert_eq!(align_manhattan(&crabs, linear), Some((vec![0, 0], 12 + 14)));
+
+        // Triangular of the whole distance against every target in the bounding box
+        let fuel = |x: isize, y: isize| {
+            crabs
+                .iter()
+                .map(|c| {
+                    triangular(0, (c.position[0] - x).abs() + (c.position[1] - y).abs()) * c.weight
+                })
+                .sum::<isize>()
+        };
+        let brute_force = (0..=10)
+            .flat_map(|x| (0..=10).map(move |y| (x, y)))
+            .map(|(x, y)| fuel(x, y))
+            .min();
+        let (target, total) = align_manhattan(&crabs, triangular).unwrap();
+        assert_eq!(Some(total), brute_force);
+        assert_eq!(fuel(target[0], target[1]), total);
+
+        assert!(crabs_from_str("1;2,3").is_err());
+        assert!(crabs_from_str("0, 10:-5").is_err());
+    }
 }
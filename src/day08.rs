@@ -1,200 +1,460 @@
 use crate::util::parse_strings;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use rayon::prelude::*;
 
-#[derive(Debug)]
-struct Code {
-    unknowns: Vec<String>,
-    knowns: [Option<String>; 10],
-    input: [String; 4],
+/// The most segments a display can have, wires and segments are bits of a `u32`
+const MAX_SEGMENTS: usize = 32;
+
+/// The most glyphs a font can have, the solver tracks them as bits of a `u128`
+const MAX_GLYPHS: usize = 128;
+
+/// The lit segments of each digit, segment `a` is bit 0 through to `g` as bit 6
+const DIGITS: [u32; 10] = [
+    0b1110111, 0b0100100, 0b1011101, 0b1101101, 0b0101110, 0b1101011, 0b1111011, 0b0100101,
+    0b1111111, 0b1101111,
+];
+
+/// Which segments light up for each glyph of a display
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Font {
+    segments: usize,
+    glyphs: Vec<(char, u32)>,
 }
 
-impl Code {
-    fn from_str(s: &str) -> Self {
-        let mut split = s.split(" | ");
-        let unknowns = split
-            .next()
-            .unwrap()
-            .split_whitespace()
-            .map(String::from)
-            .collect();
-        let mut i = split.next().unwrap().split_whitespace().map(String::from);
-        let input = [
-            i.next().unwrap(),
-            i.next().unwrap(),
-            i.next().unwrap(),
-            i.next().unwrap(),
-        ];
+impl Font {
+    /// The standard seven segment digits
+    fn digits() -> Self {
         Self {
-            unknowns,
-            knowns: Default::default(),
-            input,
+            segments: 7,
+            glyphs: DIGITS
+                .iter()
+                .enumerate()
+                .map(|(d, mask)| (std::char::from_digit(d as u32, 10).unwrap(), *mask))
+                .collect(),
         }
     }
 
-    fn resolve(&mut self) {
-        self.resolve_1478();
-        self.resolve_039();
-        self.resolve_256();
+    /// One glyph per line, the glyph followed by the letters of its lit segments, `7 acf`.
+    /// Segments are named `a` to `z` and the display has as many as the highest letter used.
+    fn parse(s: &str) -> Result<Self> {
+        let mut glyphs: Vec<(char, u32)> = Vec::new();
+        for line in s.lines().filter(|l| !l.trim().is_empty()) {
+            let mut split = line.split_whitespace();
+            let mut glyph = split.next().unwrap().chars();
+            let segments = split.next().unwrap_or("");
+            if let Some(extra) = split.next() {
+                bail!("unexpected {:?} after the segments in {:?}", extra, line);
+            }
+            let glyph = match (glyph.next(), glyph.next()) {
+                (Some(c), None) => c,
+                _ => bail!("glyph in {:?} is not a single character", line),
+            };
+            if glyphs.iter().any(|(c, _)| *c == glyph) {
+                bail!("{:?} is defined more than once", glyph);
+            }
+            if let Some(bad) = segments.chars().find(|c| !c.is_ascii_lowercase()) {
+                bail!("{:?} in {:?} is not a segment letter a-z", bad, line);
+            }
+            let mask = to_mask(segments);
+            if let Some((other, _)) = glyphs.iter().find(|(_, m)| *m == mask) {
+                bail!("{:?} and {:?} light up the same segments", other, glyph);
+            }
+            glyphs.push((glyph, mask));
+        }
+        if glyphs.len() > MAX_GLYPHS {
+            bail!("a font can have at most {} glyphs", MAX_GLYPHS);
+        }
+        let segments = glyphs
+            .iter()
+            .map(|(_, m)| (MAX_SEGMENTS as u32 - m.leading_zeros()) as usize)
+            .max()
+            .unwrap_or(0);
+        Ok(Self { segments, glyphs })
     }
 
-    fn resolve_1478(&mut self) {
-        let removed_lengths = self.unknowns.clone();
-        self.unknowns = removed_lengths
-            .into_iter()
-            .filter(|u| match u.len() {
-                2 => {
-                    self.knowns[1] = Some(u.to_string());
-                    false
-                }
-                3 => {
-                    self.knowns[7] = Some(u.to_string());
-                    false
-                }
-                4 => {
-                    self.knowns[4] = Some(u.to_string());
-                    false
+    fn glyph(&self, mask: u32) -> Option<usize> {
+        self.glyphs.iter().position(|(_, m)| *m == mask)
+    }
+}
+
+fn to_mask(pattern: &str) -> u32 {
+    pattern.bytes().fold(0, |mask, b| mask | 1 << (b - b'a'))
+}
+
+type Wiring = [u8; MAX_SEGMENTS];
+
+/// Light up the segments the wires in `mask` are connected to
+fn rewire(mask: u32, wiring: &Wiring) -> u32 {
+    (0..MAX_SEGMENTS)
+        .filter(|wire| mask & 1 << wire != 0)
+        .fold(0, |out, wire| out | 1 << wiring[wire])
+}
+
+/// For a wire (or segment) how many of the masks of each length it is part of. A wire can
+/// only drive a segment with the same profile when every glyph of the font was seen.
+fn profile(masks: impl Iterator<Item = u32>, wire: usize) -> [u8; MAX_SEGMENTS + 1] {
+    let mut profile = [0; MAX_SEGMENTS + 1];
+    for mask in masks.filter(|m| m & 1 << wire != 0) {
+        profile[mask.count_ones() as usize] += 1;
+    }
+    profile
+}
+
+/// Finds the wire to segment permutation that turns scrambled patterns into glyphs
+struct Solver<'a> {
+    font: &'a Font,
+    patterns: &'a [u32],
+    candidates: [u32; MAX_SEGMENTS],
+    wiring: Wiring,
+    found: Option<Wiring>,
+    count: usize,
+}
+
+/// Find the wiring for `patterns` on a display using `font`. The candidate segments of each
+/// wire are narrowed down by their profiles, then a search assigns the wires one at a time and
+/// backs off as soon as a pattern can no longer become a glyph. It is an error if no wiring or
+/// more than one wiring fits.
+fn solve(font: &Font, patterns: &[u32]) -> Result<Wiring> {
+    let n = font.segments;
+    let all = if n == MAX_SEGMENTS { !0 } else { (1 << n) - 1 };
+    let mut candidates = [0; MAX_SEGMENTS];
+    for (wire, candidate) in candidates.iter_mut().enumerate().take(n) {
+        *candidate = all;
+        if patterns.len() == font.glyphs.len() {
+            let wire_profile = profile(patterns.iter().cloned(), wire);
+            *candidate = (0..n)
+                .filter(|s| profile(font.glyphs.iter().map(|g| g.1), *s) == wire_profile)
+                .fold(0, |c, segment| c | 1 << segment);
+        }
+    }
+    if patterns.iter().any(|p| p & !all != 0) {
+        bail!(
+            "patterns {:?} use wires the display does not have",
+            patterns
+        );
+    }
+
+    let mut solver = Solver {
+        font,
+        patterns,
+        candidates,
+        wiring: [0; MAX_SEGMENTS],
+        found: None,
+        count: 0,
+    };
+    solver.search(0, 0);
+    match (solver.count, solver.found) {
+        (1, Some(wiring)) => Ok(wiring),
+        (0, _) => bail!("no wiring matches the patterns {:?}", patterns),
+        _ => bail!("more than one wiring matches the patterns {:?}", patterns),
+    }
+}
+
+impl<'a> Solver<'a> {
+    /// Can every pattern still become a glyph of its size, given the segments the first
+    /// `wires` wires are connected to
+    fn consistent(&self, wires: usize, used: u32) -> bool {
+        let assigned = if wires == MAX_SEGMENTS {
+            !0
+        } else {
+            (1 << wires) - 1
+        };
+        self.patterns.iter().all(|p| {
+            let lit = rewire(p & assigned, &self.wiring);
+            self.font
+                .glyphs
+                .iter()
+                .any(|(_, g)| g.count_ones() == p.count_ones() && g & used == lit)
+        })
+    }
+
+    /// Try every free candidate segment for `wire`, stops once a second solution is found
+    fn search(&mut self, wire: usize, used: u32) {
+        if self.count > 1 {
+            return;
+        }
+        if wire == self.font.segments {
+            let mut seen = 0u128;
+            for pattern in self.patterns {
+                match self.font.glyph(rewire(*pattern, &self.wiring)) {
+                    Some(glyph) if seen & 1 << glyph == 0 => seen |= 1 << glyph,
+                    _ => return,
                 }
-                7 => {
-                    self.knowns[8] = Some(u.to_string());
-                    false
+            }
+            self.count += 1;
+            self.found = Some(self.wiring);
+            return;
+        }
+        for segment in 0..self.font.segments {
+            let bit = 1 << segment;
+            if self.candidates[wire] & bit != 0 && used & bit == 0 {
+                self.wiring[wire] = segment as u8;
+                if self.consistent(wire + 1, used | bit) {
+                    self.search(wire + 1, used | bit);
                 }
-                _ => true,
-            })
-            .collect();
+            }
+        }
     }
+}
 
-    fn resolve_039(&mut self) {
-        let cf = self.knowns[1].clone().unwrap();
-        let cf: Vec<char> = cf.chars().collect();
-        // This essentially contains the code for 0,3 and 9. the only unknowns that have the 2
-        // right ones (c,f in a standard cnfig)
-        let unknowns_with_cf: Vec<String> = self
-            .unknowns
-            .clone()
-            .into_iter()
-            .filter(|x| x.contains(cf[0]) && x.contains(cf[1]))
-            .collect();
-        let three = unknowns_with_cf
-            .iter()
-            .find(|x| x.len() == 5)
-            .cloned()
-            .unwrap();
-        let resolved: Vec<(String, usize)> = unknowns_with_cf
-            .into_iter()
-            .map(|x| {
-                let num_diff = x
-                    .chars()
-                    .map(|c| if three.contains(c) { 0 } else { 1 })
-                    .sum();
-                let number = match num_diff {
-                    0 => 3,
-                    1 => 9,
-                    2 => 0,
-                    _ => unreachable!(),
-                };
-                (x, number)
-            })
-            .collect();
-        self.unknowns = self
-            .unknowns
-            .clone()
-            .into_iter()
-            .filter(|x| resolved.iter().find(|(s, _)| s == x).is_none())
-            .collect();
-        resolved
-            .into_iter()
-            .for_each(|(coded, number)| self.knowns[number] = Some(coded));
+/// Decode `outputs` into glyphs once the wiring is worked out from `patterns`
+fn decode(font: &Font, patterns: &[u32], outputs: &[u32]) -> Result<String> {
+    let wiring = solve(font, patterns)?;
+    outputs
+        .iter()
+        .map(|o| match font.glyph(rewire(*o, &wiring)) {
+            Some(glyph) => Ok(font.glyphs[glyph].0),
+            None => bail!("output {:#b} is not a glyph", o),
+        })
+        .collect()
+}
+
+/// How much less likely every corrupted observation makes a wiring
+const NOISE_ODDS: f64 = 20.0;
+
+/// The most likely reading of an entry where some observations may be missing or have one
+/// segment flipped
+#[derive(Debug, Clone, PartialEq)]
+struct NoisyDecoding {
+    wiring: Wiring,
+    /// The output glyphs, `?` where an output is more than one segment off any glyph
+    output: String,
+    /// Share of the likelihood over every wiring held by wirings reading the same output
+    confidence: f64,
+    /// Indices of the patterns that did not exactly match a glyph
+    corrupted_patterns: Vec<usize>,
+    /// Indices of the outputs that did not exactly match a glyph
+    corrupted_outputs: Vec<usize>,
+}
+
+/// Read `mask` as a glyph, falling back to the only glyph one segment away. Also returns how
+/// far off it was, 0 for a clean match, 1 for one flipped segment and 2 for anything worse.
+fn nearest_glyph(font: &Font, mask: u32) -> (char, usize) {
+    if let Some(glyph) = font.glyph(mask) {
+        return (font.glyphs[glyph].0, 0);
+    }
+    let mut close = font
+        .glyphs
+        .iter()
+        .filter(|(_, g)| (g ^ mask).count_ones() == 1);
+    match (close.next(), close.next()) {
+        (Some((c, _)), None) => (*c, 1),
+        (Some(_), Some(_)) => ('?', 1),
+        _ => ('?', 2),
     }
+}
 
-    fn resolve_256(&mut self) {
-        let six = self
-            .unknowns
-            .iter()
-            .find(|x| x.len() == 6)
-            .cloned()
-            .unwrap();
-        let nine = self.knowns[9].clone().unwrap();
-        let resolved: Vec<(String, usize)> = self
-            .unknowns
-            .clone()
-            .into_iter()
-            .filter(|x| *x != six)
-            .map(|x| {
-                let num_diff = x
-                    .chars()
-                    .map(|c| if nine.contains(c) { 0 } else { 1 })
-                    .sum();
-                let number = match num_diff {
-                    0 => 5,
-                    1 => 2,
-                    _ => unreachable!(),
-                };
-                (x, number)
-            })
-            .collect();
-        resolved
-            .into_iter()
-            .for_each(|(coded, number)| self.knowns[number] = Some(coded));
-        self.knowns[6] = Some(six);
-        self.unknowns = vec![];
+/// Call `f` with every wiring of the first `n` wires
+fn for_each_wiring(
+    n: usize,
+    wire: usize,
+    used: u32,
+    wiring: &mut Wiring,
+    f: &mut dyn FnMut(&Wiring),
+) {
+    if wire == n {
+        f(wiring);
+        return;
     }
+    for segment in 0..n {
+        if used & 1 << segment == 0 {
+            wiring[wire] = segment as u8;
+            for_each_wiring(n, wire + 1, used | 1 << segment, wiring, f);
+        }
+    }
+}
 
-    fn try_decoding(&self, encoded: &str) -> Option<usize> {
-        let mut options: Vec<(usize, String)> = self
-            .knowns
+/// Score every wiring by how many observations it leaves off a glyph and pick the most
+/// likely output. Every wiring is tried, so this is limited to fonts of up to 8 segments.
+fn decode_noisy(font: &Font, patterns: &[u32], outputs: &[u32]) -> Result<NoisyDecoding> {
+    if font.segments > 8 {
+        bail!(
+            "noisy decoding needs a font of at most 8 segments, not {}",
+            font.segments
+        );
+    }
+    // Likelihood of each output string and the best scoring wiring that reads it
+    let mut readings: Vec<(String, f64, usize, Wiring)> = Vec::new();
+    let mut total = 0.0;
+    for_each_wiring(font.segments, 0, 0, &mut [0; MAX_SEGMENTS], &mut |wiring| {
+        let mut errors = patterns
+            .iter()
+            .map(|p| nearest_glyph(font, rewire(*p, wiring)).1)
+            .sum::<usize>();
+        let mut output = String::new();
+        for o in outputs {
+            let (glyph, error) = nearest_glyph(font, rewire(*o, wiring));
+            output.push(glyph);
+            errors += error;
+        }
+        let weight = NOISE_ODDS.powi(-(errors as i32));
+        total += weight;
+        match readings.iter_mut().find(|r| r.0 == output) {
+            Some(reading) => {
+                reading.1 += weight;
+                if errors < reading.2 {
+                    reading.2 = errors;
+                    reading.3 = *wiring;
+                }
+            }
+            None => readings.push((output, weight, errors, *wiring)),
+        }
+    });
+    let (output, weight, _, wiring) = readings
+        .into_iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(b.2.cmp(&a.2)))
+        .unwrap();
+    let corrupted = |masks: &[u32]| -> Vec<usize> {
+        masks
             .iter()
             .enumerate()
-            .filter(|(_, val)| val.is_some())
-            .map(|(i, x)| (i, x.clone().unwrap()))
-            .filter(|(_, x)| x.len() == encoded.len())
-            .collect();
-        for c in encoded.chars() {
-            options = options.into_iter().filter(|(_, x)| x.contains(c)).collect();
+            .filter(|(_, m)| nearest_glyph(font, rewire(**m, &wiring)).1 > 0)
+            .map(|(idx, _)| idx)
+            .collect()
+    };
+    Ok(NoisyDecoding {
+        wiring,
+        output,
+        confidence: weight / total,
+        corrupted_patterns: corrupted(patterns),
+        corrupted_outputs: corrupted(outputs),
+    })
+}
+
+/// One entry of the puzzle, every pattern a bitmask of its wires with wire `a` as bit 0. Up
+/// to ten patterns are kept so entries with missing patterns can still be read noisily.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Code {
+    patterns: [u8; 10],
+    num_patterns: usize,
+    input: [u8; 4],
+}
+
+fn wire_mask(pattern: &str) -> Result<u8> {
+    pattern.bytes().try_fold(0, |mask, b| match b {
+        b'a'..=b'g' => Ok(mask | 1 << (b - b'a')),
+        _ => bail!("{:?} is not a wire of a seven segment display", pattern),
+    })
+}
+
+impl Code {
+    fn from_str(s: &str) -> Result<Self> {
+        let mut split = s.split(" | ");
+        let (patterns_str, input_str) = match (split.next(), split.next()) {
+            (Some(p), Some(i)) => (p, i),
+            _ => bail!("{:?} is not `patterns | input`", s),
+        };
+        let mut patterns = [0; 10];
+        let mut num_patterns = 0;
+        for pattern in patterns_str.split_whitespace() {
+            if num_patterns == patterns.len() {
+                bail!("more than ten patterns in {:?}", s);
+            }
+            patterns[num_patterns] = wire_mask(pattern)?;
+            num_patterns += 1;
         }
-        if options.len() == 1 {
-            Some(options[0].0)
-        } else {
-            None
+        let mut input = [0; 4];
+        let mut num_input = 0;
+        for output in input_str.split_whitespace() {
+            if num_input == input.len() {
+                bail!("more than four outputs in {:?}", s);
+            }
+            input[num_input] = wire_mask(output)?;
+            num_input += 1;
+        }
+        if num_input != input.len() {
+            bail!("fewer than four outputs in {:?}", s);
         }
+        Ok(Self {
+            patterns,
+            num_patterns,
+            input,
+        })
     }
 
-    fn num_parsed(&self) -> usize {
-        self.input
-            .iter()
-            .map(|encoded| self.try_decoding(encoded))
-            .filter(Option::is_some)
-            //.for_each(|x| { dbg!(x); })
-            .count()
+    fn patterns(&self) -> &[u8] {
+        &self.patterns[..self.num_patterns]
     }
 
-    fn decode_input(&self) -> usize {
-        let decoded: Vec<usize> = self
-            .input
-            .iter()
-            .map(|x| self.try_decoding(x).unwrap())
-            .collect();
-        decoded[0] * 1000 + decoded[1] * 100 + decoded[2] * 10 + decoded[3]
+    /// The digit `encoded` shows, solving the wiring on the way
+    fn try_decoding(&self, font: &Font, encoded: u8) -> Option<usize> {
+        let (patterns, _) = self.wide_masks();
+        let wiring = solve(font, &patterns[..self.num_patterns]).ok()?;
+        font.glyph(rewire(encoded as u32, &wiring))
+    }
+
+    /// The four digits of the input, `font` being `Font::digits` so glyph `i` is digit `i`
+    fn output(&self, font: &Font) -> Result<[usize; 4]> {
+        let (patterns, input) = self.wide_masks();
+        let wiring = solve(font, &patterns[..self.num_patterns])?;
+        let mut output = [0; 4];
+        for (out, encoded) in output.iter_mut().zip(input) {
+            *out = match font.glyph(rewire(encoded, &wiring)) {
+                Some(digit) => digit,
+                None => bail!("output {:#b} of {:?} is not a digit", encoded, self),
+            };
+        }
+        Ok(output)
+    }
+
+    fn wide_masks(&self) -> ([u32; 10], [u32; 4]) {
+        let mut patterns = [0; 10];
+        let mut input = [0; 4];
+        patterns
+            .iter_mut()
+            .zip(self.patterns())
+            .for_each(|(w, p)| *w = *p as u32);
+        input
+            .iter_mut()
+            .zip(self.input)
+            .for_each(|(w, p)| *w = p as u32);
+        (patterns, input)
+    }
+
+    /// The input as a string of glyphs of any seven segment `font`
+    fn decode_input(&self, font: &Font) -> Result<String> {
+        let (patterns, input) = self.wide_masks();
+        decode(font, &patterns[..self.num_patterns], &input)
+    }
+
+    /// Like `decode_input` but tolerates missing and slightly corrupted patterns
+    fn decode_noisy(&self, font: &Font) -> Result<NoisyDecoding> {
+        let (patterns, input) = self.wide_masks();
+        decode_noisy(font, &patterns[..self.num_patterns], &input)
     }
 }
 
 fn parse(filename: &str) -> Result<Vec<Code>> {
     let strings = parse_strings(filename)?;
-    let result = strings.iter().map(|s| Code::from_str(s)).collect();
-    Ok(result)
+    strings.iter().map(|s| Code::from_str(s)).collect()
+}
+
+/// How many outputs are a 1, 4, 7 or 8
+fn count_easy_digits(coded: &[Code]) -> Result<usize> {
+    let font = Font::digits();
+    coded
+        .par_iter()
+        .map(|code| {
+            let output = code.output(&font)?;
+            Ok(output.iter().filter(|d| matches!(d, 1 | 4 | 7 | 8)).count())
+        })
+        .sum()
+}
+
+fn sum_outputs(coded: &[Code]) -> Result<usize> {
+    let font = Font::digits();
+    coded
+        .par_iter()
+        .map(|code| Ok(code.output(&font)?.iter().fold(0, |n, d| n * 10 + d)))
+        .sum()
 }
 
 fn q1(filename: &str) -> Result<usize> {
-    let mut coded = parse(filename)?;
-    coded.iter_mut().for_each(Code::resolve_1478);
-    let result = coded.iter().map(Code::num_parsed).sum();
-    Ok(result)
+    count_easy_digits(&parse(filename)?)
 }
 
 fn q2(filename: &str) -> Result<usize> {
-    let mut coded = parse(filename)?;
-    coded.iter_mut().for_each(Code::resolve);
-    let result = coded.iter().map(Code::decode_input).sum();
-    Ok(result)
+    sum_outputs(&parse(filename)?)
 }
 
 #[cfg(test)]
@@ -207,4 +467,98 @@ mod tests {
         assert_eq!(q1("./data/day08.txt").unwrap(), 342);
         assert_eq!(q2("./data/day08.txt").unwrap(), 1068933);
     }
+
+    #[test]
+    fn test_solve() {
+        let code = Code::from_str(
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
+        )
+        .unwrap();
+        let digits = Font::digits();
+        assert_eq!(
+            code.try_decoding(&digits, wire_mask("cdfeb").unwrap()),
+            Some(5)
+        );
+        assert_eq!(code.output(&digits).unwrap(), [5, 3, 5, 3]);
+        assert_eq!(code.decode_input(&digits).unwrap(), "5353");
+        assert!(Code::from_str("ab abc | ab ab ab").is_err());
+        assert!(Code::from_str("ab abz | ab ab ab ab").is_err());
+
+        // Two different three segment patterns cannot both be a 7
+        let broken: Vec<u32> = ["ab", "abc", "abd", "abcdefg"]
+            .iter()
+            .map(|p| to_mask(p))
+            .collect();
+        assert!(solve(&Font::digits(), &broken).is_err());
+        // A lone 1 fits many wirings
+        assert!(solve(&Font::digits(), &[to_mask("ab")]).is_err());
+
+        // `dge` in place of `dab` leaves no wiring that turns the patterns into the digits
+        let inconsistent = [Code::from_str(
+            "acedgfb cdfbe gcdfa fbcad dge cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb dge",
+        )
+        .unwrap()];
+        assert!(count_easy_digits(&inconsistent).is_err());
+        assert!(sum_outputs(&inconsistent).is_err());
+    }
+
+    #[test]
+    fn test_font() {
+        // A fourteen segment font: a-f around the edge, g and h the middle bars, i-k the
+        // upper diagonals and vertical, l-n the lower ones
+        let font = Font::parse(
+            "4 bcfgh\nA abcefgh\nC adef\nD abcdjm\nE adefg\nH bcefgh\nI adjm\nJ bcde\n\
+             K efgkn\nL def\nM bcefik\nN bcefin\nO abcdef\nR abefghn\nT ajm\nV efln\n\
+             W bcefln\nX ikln\nY ikm\nZ adkl\n",
+        )
+        .unwrap();
+        assert_eq!(font.segments, 14);
+
+        // Scramble the wires by reversing them
+        let scramble = |mask: u32| {
+            (0..14)
+                .filter(|w| mask & 1 << w != 0)
+                .fold(0, |m, w| m | 1 << (13 - w))
+        };
+        let mut patterns: Vec<u32> = font.glyphs.iter().map(|g| scramble(g.1)).collect();
+        patterns.reverse();
+        let word = |w: &str| -> Vec<u32> {
+            w.chars()
+                .map(|c| scramble(font.glyphs.iter().find(|g| g.0 == c).unwrap().1))
+                .collect()
+        };
+        assert_eq!(
+            decode(&font, &patterns, &word("HELLOWORLD")).unwrap(),
+            "HELLOWORLD"
+        );
+
+        assert!(Font::parse("0 abc\nO abc").is_err());
+        assert!(Font::parse("0 ab{").is_err());
+        assert!(Font::parse("0 aB").is_err());
+        assert!(Font::parse("7 ac f").is_err());
+        assert!(Font::parse("7 acf\n7 abc").is_err());
+    }
+
+    #[test]
+    fn test_noisy() {
+        let font = Font::digits();
+        let clean = Code::from_str(
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
+        )
+        .unwrap();
+        let decoded = clean.decode_noisy(&font).unwrap();
+        assert_eq!(decoded.output, "5353");
+        assert!(decoded.corrupted_patterns.is_empty() && decoded.corrupted_outputs.is_empty());
+        assert!(decoded.confidence > 0.9);
+
+        // The 0 and the 8 are missing, the 9 lost a segment and so did the 7 in the output
+        let noisy =
+            Code::from_str("cdfbe gcdfa fbcad dab cefab cdfgeb eafb ab | cdfeb fcadb cdfeb da")
+                .unwrap();
+        let decoded = noisy.decode_noisy(&font).unwrap();
+        assert_eq!(decoded.output, "5357");
+        assert_eq!(decoded.corrupted_patterns, vec![4]);
+        assert_eq!(decoded.corrupted_outputs, vec![3]);
+        assert!(decoded.confidence < 1.0);
+    }
 }
@@ -1,7 +1,6 @@
-use std::collections::HashSet;
-
 use crate::util::parse_strings;
 use anyhow::Result;
+use std::{cmp::Reverse, collections::BinaryHeap};
 
 type HeightMap = Vec<Vec<u32>>;
 
@@ -44,52 +43,352 @@ fn q1(filename: &str) -> Result<u32> {
     Ok(total_risk)
 }
 
-fn non_nine_neighbors(hm: &HeightMap, coord: Coord) -> Vec<Coord> {
-    let num_rows = hm.len();
-    let num_cols = hm[0].len();
-    let mut results = Vec::new();
+/// Which cells count as touching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Connectivity {
+    Four,
+    Eight,
+}
+
+/// What happens past the edge of the map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edges {
+    Bounded,
+    Wrapping,
+}
+
+/// How a height map splits up into basins: cells at or above `wall` separate them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Terrain {
+    wall: u32,
+    connectivity: Connectivity,
+    edges: Edges,
+}
+
+/// The rules from the puzzle, 9s are walls and cells only touch along an edge
+const PUZZLE: Terrain = Terrain {
+    wall: 9,
+    connectivity: Connectivity::Four,
+    edges: Edges::Bounded,
+};
 
-    if coord.0 != 0 && hm[coord.0 - 1][coord.1] != 9 {
-        results.push((coord.0 - 1, coord.1));
+impl Terrain {
+    fn is_wall(&self, height: u32) -> bool {
+        height >= self.wall
     }
-    if coord.0 != num_rows - 1 && hm[coord.0 + 1][coord.1] != 9 {
-        results.push((coord.0 + 1, coord.1));
+
+    /// Every cell touching `coord`, walls included
+    fn neighbors(&self, hm: &HeightMap, coord: Coord) -> Vec<Coord> {
+        let num_rows = hm.len() as isize;
+        let num_cols = hm[0].len() as isize;
+        let mut results = Vec::new();
+        for dr in -1..=1 {
+            for dc in -1..=1 {
+                let diagonal = dr != 0 && dc != 0;
+                if (dr == 0 && dc == 0) || (diagonal && self.connectivity == Connectivity::Four) {
+                    continue;
+                }
+                let (r, c) = (coord.0 as isize + dr, coord.1 as isize + dc);
+                let neighbor = match self.edges {
+                    Edges::Bounded if r < 0 || r >= num_rows || c < 0 || c >= num_cols => continue,
+                    Edges::Bounded => (r as usize, c as usize),
+                    Edges::Wrapping => (
+                        r.rem_euclid(num_rows) as usize,
+                        c.rem_euclid(num_cols) as usize,
+                    ),
+                };
+                if neighbor != coord && !results.contains(&neighbor) {
+                    results.push(neighbor);
+                }
+            }
+        }
+        results
     }
-    if coord.1 != 0 && hm[coord.0][coord.1 - 1] != 9 {
-        results.push((coord.0, coord.1 - 1));
+
+    fn non_wall_neighbors(&self, hm: &HeightMap, coord: Coord) -> Vec<Coord> {
+        self.neighbors(hm, coord)
+            .into_iter()
+            .filter(|(r, c)| !self.is_wall(hm[*r][*c]))
+            .collect()
     }
-    if coord.1 != num_cols - 1 && hm[coord.0][coord.1 + 1] != 9 {
-        results.push((coord.0, coord.1 + 1));
+
+    /// Whether water at `coord` can run off the map
+    fn on_edge(&self, hm: &HeightMap, coord: Coord) -> bool {
+        self.edges == Edges::Bounded
+            && (coord.0 == 0
+                || coord.1 == 0
+                || coord.0 == hm.len() - 1
+                || coord.1 == hm[0].len() - 1)
     }
-    results
 }
 
-fn basin_size(hm: &HeightMap, coord: Coord) -> usize {
-    let mut basin = HashSet::new();
-    let mut to_check = vec![coord];
-    while to_check.len() > 0 {
-        let check = to_check.pop().unwrap();
-        let nnn = non_nine_neighbors(hm, check);
-        for coord in nnn {
-            if !basin.contains(&coord) {
-                basin.insert(coord);
-                to_check.push(coord);
+/// Union-find over the cells of a height map, flattened row by row
+struct DisjointSet {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Basin {
+    members: Vec<Coord>,
+    /// Every member at the lowest height of the basin, a flat bottom gives several
+    low_points: Vec<Coord>,
+}
+
+/// Every non wall cell labelled with its basin. A basin is a group of non wall cells that
+/// touch, so a basin with a plateau at the bottom is still a single basin.
+#[derive(Debug)]
+struct Basins {
+    labels: Vec<Vec<Option<usize>>>,
+    basins: Vec<Basin>,
+}
+
+fn label_basins(hm: &HeightMap, terrain: &Terrain) -> Basins {
+    let num_rows = hm.len();
+    let num_cols = hm.first().map_or(0, Vec::len);
+    let idx = |(r, c): Coord| r * num_cols + c;
+    let mut sets = DisjointSet::new(num_rows * num_cols);
+    for row in 0..num_rows {
+        for col in 0..num_cols {
+            if !terrain.is_wall(hm[row][col]) {
+                for neighbor in terrain.non_wall_neighbors(hm, (row, col)) {
+                    sets.union(idx((row, col)), idx(neighbor));
+                }
+            }
+        }
+    }
+
+    let mut labels = vec![vec![None; num_cols]; num_rows];
+    let mut basins: Vec<Basin> = Vec::new();
+    let mut label_of_root = vec![None; num_rows * num_cols];
+    for row in 0..num_rows {
+        for col in 0..num_cols {
+            if terrain.is_wall(hm[row][col]) {
+                continue;
+            }
+            let root = sets.find(idx((row, col)));
+            let label = *label_of_root[root].get_or_insert_with(|| {
+                basins.push(Basin {
+                    members: Vec::new(),
+                    low_points: Vec::new(),
+                });
+                basins.len() - 1
+            });
+            labels[row][col] = Some(label);
+            let basin = &mut basins[label];
+            basin.members.push((row, col));
+            match basin.low_points.first().map(|(r, c)| hm[*r][*c]) {
+                Some(low) if low < hm[row][col] => {}
+                Some(low) if low == hm[row][col] => basin.low_points.push((row, col)),
+                _ => basin.low_points = vec![(row, col)],
             }
         }
     }
+    Basins { labels, basins }
+}
 
-    basin.len()
+/// How high the water stands in a basin filled up to its spill point and how much it holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fill {
+    level: u32,
+    volume: u64,
+}
+
+/// Fill every basin with rain until it spills. Water leaves a basin by running off the map
+/// from a cell on a bounded edge or by reaching another basin, and the spill level is the
+/// lowest, over every such way out, of the highest cell along it. The basin is treated as one
+/// pool, the water stands at the spill level over every cell lower than it that it can reach,
+/// pockets in the walls included. `None` for a basin with no way out that never spills.
+fn rain_fill(hm: &HeightMap, terrain: &Terrain, basins: &Basins) -> Vec<Option<Fill>> {
+    (0..basins.basins.len())
+        .map(|label| {
+            let level = spill_level(hm, terrain, basins, label)?;
+            let mut seen = vec![vec![false; hm[0].len()]; hm.len()];
+            let mut stack: Vec<Coord> = basins.basins[label]
+                .members
+                .iter()
+                .filter(|(r, c)| hm[*r][*c] < level)
+                .cloned()
+                .collect();
+            stack.iter().for_each(|(r, c)| seen[*r][*c] = true);
+            let mut volume = 0;
+            while let Some((row, col)) = stack.pop() {
+                volume += (level - hm[row][col]) as u64;
+                for (r, c) in terrain.neighbors(hm, (row, col)) {
+                    if !seen[r][c] && hm[r][c] < level {
+                        seen[r][c] = true;
+                        stack.push((r, c));
+                    }
+                }
+            }
+            Some(Fill { level, volume })
+        })
+        .collect()
+}
+
+/// The lowest height water in basin `label` has to rise to before it gets out, found by
+/// always extending the way out whose highest cell is lowest so far (Dijkstra on the max
+/// height instead of the sum)
+fn spill_level(hm: &HeightMap, terrain: &Terrain, basins: &Basins, label: usize) -> Option<u32> {
+    let mut seen = vec![vec![false; hm[0].len()]; hm.len()];
+    let mut queue: BinaryHeap<Reverse<(u32, Coord)>> = basins.basins[label]
+        .members
+        .iter()
+        .map(|(r, c)| Reverse((hm[*r][*c], (*r, *c))))
+        .collect();
+    while let Some(Reverse((level, (row, col)))) = queue.pop() {
+        if seen[row][col] {
+            continue;
+        }
+        seen[row][col] = true;
+        let other_basin = matches!(basins.labels[row][col], Some(l) if l != label);
+        if other_basin || terrain.on_edge(hm, (row, col)) {
+            return Some(level);
+        }
+        for (r, c) in terrain.neighbors(hm, (row, col)) {
+            if !seen[r][c] {
+                queue.push(Reverse((level.max(hm[r][c]), (r, c))));
+            }
+        }
+    }
+    None
+}
+
+impl Basins {
+    /// Labels of the `n` biggest basins, biggest first
+    fn largest(&self, n: usize) -> Vec<usize> {
+        let mut labels: Vec<usize> = (0..self.basins.len()).collect();
+        labels.sort_by_key(|l| Reverse(self.basins[*l].members.len()));
+        labels.truncate(n);
+        labels
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+const WALL_COLOUR: Rgb = (40, 40, 40);
+const LOW_POINT_COLOUR: Rgb = (255, 255, 255);
+
+/// A colour per basin, stepping around the hue wheel by the golden angle so neighbouring
+/// labels look different. Basins that are not `highlighted` are dimmed.
+fn basin_colour(label: usize, highlighted: bool) -> Rgb {
+    let hue = (label as f64 * 137.508) % 360.0;
+    let x = 1.0 - ((hue / 60.0) % 2.0 - 1.0).abs();
+    let (r, g, b) = match (hue / 60.0) as usize {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let scale = if highlighted { 255.0 } else { 90.0 };
+    let channel = |v: f64| (v * scale).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+/// The colour of every cell: walls are dark grey, low points white and the three largest
+/// basins (the ones `q2` multiplies) in full colour while the rest are dimmed
+fn colour_map(hm: &HeightMap, basins: &Basins) -> Vec<Vec<Rgb>> {
+    let largest = basins.largest(3);
+    hm.iter()
+        .enumerate()
+        .map(|(row, heights)| {
+            (0..heights.len())
+                .map(|col| match basins.labels[row][col] {
+                    None => WALL_COLOUR,
+                    Some(label) if basins.basins[label].low_points.contains(&(row, col)) => {
+                        LOW_POINT_COLOUR
+                    }
+                    Some(label) => basin_colour(label, largest.contains(&label)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The heights drawn over their cell colours with ANSI true colour escapes, low points are
+/// also shown in bold
+fn render_ansi(hm: &HeightMap, basins: &Basins) -> String {
+    let mut out = String::new();
+    for (row, colours) in colour_map(hm, basins).iter().enumerate() {
+        for (col, (r, g, b)) in colours.iter().enumerate() {
+            let low = (*r, *g, *b) == LOW_POINT_COLOUR;
+            out += &format!(
+                "\x1b[{}38;2;0;0;0;48;2;{};{};{}m{}",
+                if low { "1;" } else { "" },
+                r,
+                g,
+                b,
+                hm[row][col]
+            );
+        }
+        out += "\x1b[0m\n";
+    }
+    out
+}
+
+/// Plain (P3) colour image with every cell drawn as a `scale` by `scale` square
+fn ppm(hm: &HeightMap, basins: &Basins, scale: usize) -> String {
+    let colours = colour_map(hm, basins);
+    let width = colours.first().map_or(0, Vec::len) * scale;
+    let mut image = format!("P3\n{} {}\n255\n", width, colours.len() * scale);
+    for row in &colours {
+        let line: Vec<String> = row
+            .iter()
+            .flat_map(|(r, g, b)| std::iter::repeat_n(format!("{} {} {}", r, g, b), scale))
+            .collect();
+        let line = line.join(" ");
+        for _ in 0..scale {
+            image += &line;
+            image.push('\n');
+        }
+    }
+    image
+}
+
+fn write_ppm(hm: &HeightMap, basins: &Basins, filename: &str, scale: usize) -> Result<()> {
+    std::fs::write(filename, ppm(hm, basins, scale))?;
+    Ok(())
 }
 
 fn q2(filename: &str) -> Result<usize> {
     let height_map = parse(filename)?;
-    let lowest_points = find_lowest_points(&height_map);
-    let mut result: Vec<usize> = lowest_points
+    let basins = label_basins(&height_map, &PUZZLE);
+    let product = basins
+        .largest(3)
         .iter()
-        .map(|x| basin_size(&height_map, *x))
-        .collect();
-    result.sort();
-    let product = result.into_iter().rev().take(3).product();
+        .map(|l| basins.basins[*l].members.len())
+        .product();
     Ok(product)
 }
 
@@ -103,4 +402,128 @@ mod tests {
         assert_eq!(q1("./data/day09.txt").unwrap(), 512);
         assert_eq!(q2("./data/day09.txt").unwrap(), 1600104);
     }
+
+    #[test]
+    fn test_basins() {
+        let height_map = parse("./data/day09.txt").unwrap();
+        let basins = label_basins(&height_map, &PUZZLE);
+        let mut lowest: Vec<Coord> = basins
+            .basins
+            .iter()
+            .flat_map(|b| b.low_points.clone())
+            .collect();
+        lowest.sort_unstable();
+        assert_eq!(lowest, find_lowest_points(&height_map));
+
+        // A flat bottomed basin has two low points which strict `<` misses
+        let plateau = vec![vec![3, 1, 1, 9, 2], vec![4, 5, 6, 9, 4]];
+        let basins = label_basins(&plateau, &PUZZLE);
+        assert!(find_lowest_points(&plateau).len() == 1);
+        assert_eq!(basins.basins.len(), 2);
+        assert_eq!(basins.basins[0].low_points, vec![(0, 1), (0, 2)]);
+        assert_eq!(basins.basins[0].members.len(), 6);
+        assert_eq!(basins.labels[0][3], None);
+        assert_eq!(basins.labels[1][4], Some(1));
+    }
+
+    #[test]
+    fn test_terrain() {
+        let hm = vec![vec![1, 9, 2, 9], vec![9, 3, 9, 9], vec![5, 9, 7, 4]];
+        let count = |terrain: Terrain| label_basins(&hm, &terrain).basins.len();
+        assert_eq!(count(PUZZLE), 5);
+        let eight = Terrain {
+            connectivity: Connectivity::Eight,
+            ..PUZZLE
+        };
+        assert_eq!(count(eight), 1);
+        let wrapping = Terrain {
+            edges: Edges::Wrapping,
+            ..PUZZLE
+        };
+        assert_eq!(count(wrapping), 2);
+        let low_walls = Terrain { wall: 5, ..PUZZLE };
+        assert_eq!(count(low_walls), 4);
+
+        // A bowl with a 6 on its rim, which is only a way out when it is on a bounded edge
+        let bowl = vec![
+            vec![9, 9, 9, 9],
+            vec![9, 1, 2, 6],
+            vec![9, 3, 9, 9],
+            vec![9, 9, 9, 9],
+        ];
+        let walls = Terrain { wall: 6, ..PUZZLE };
+        let basins = label_basins(&bowl, &walls);
+        assert_eq!(
+            rain_fill(&bowl, &walls, &basins),
+            vec![Some(Fill {
+                level: 6,
+                volume: 5 + 4 + 3
+            })]
+        );
+        let closed = Terrain {
+            edges: Edges::Wrapping,
+            ..walls
+        };
+        let basins = label_basins(&bowl, &closed);
+        assert_eq!(rain_fill(&bowl, &closed, &basins), vec![None]);
+        // Without walls at all the water runs off the edge at the lowest edge cell
+        let open = Terrain { wall: 10, ..PUZZLE };
+        let basins = label_basins(&bowl, &open);
+        assert_eq!(rain_fill(&bowl, &open, &basins)[0].unwrap().level, 6);
+
+        // A low wall cell boxed in by higher walls is a pocket the water fills, not a way out
+        let pocket = vec![
+            vec![9, 9, 9, 9, 9],
+            vec![9, 1, 6, 9, 9],
+            vec![9, 9, 9, 9, 9],
+        ];
+        let basins = label_basins(&pocket, &walls);
+        assert_eq!(
+            rain_fill(&pocket, &walls, &basins),
+            vec![Some(Fill {
+                level: 9,
+                volume: 8 + 3
+            })]
+        );
+
+        // Two basins behind a thick wall spill into each other over its highest cell
+        let thick = vec![
+            vec![9, 9, 9, 9, 9, 9],
+            vec![9, 1, 7, 8, 2, 9],
+            vec![9, 9, 9, 9, 9, 9],
+        ];
+        let walls = Terrain { wall: 7, ..PUZZLE };
+        let basins = label_basins(&thick, &walls);
+        assert_eq!(
+            rain_fill(&thick, &walls, &basins),
+            vec![
+                Some(Fill {
+                    level: 8,
+                    volume: 7 + 1
+                }),
+                Some(Fill {
+                    level: 8,
+                    volume: 6
+                })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let hm = vec![vec![1, 2, 9, 0], vec![3, 9, 9, 1]];
+        let basins = label_basins(&hm, &PUZZLE);
+        let colours = colour_map(&hm, &basins);
+        assert_eq!(colours[0][0], LOW_POINT_COLOUR);
+        assert_eq!(colours[0][2], WALL_COLOUR);
+        assert_eq!(colours[0][1], basin_colour(0, true));
+
+        let ansi = render_ansi(&hm, &basins);
+        assert_eq!(ansi.lines().count(), 2);
+        assert!(ansi.starts_with("\x1b[1;38;2;0;0;0;48;2;255;255;255m1"));
+
+        let image = ppm(&hm, &basins, 2);
+        assert!(image.starts_with("P3\n8 4\n255\n255 255 255 255 255 255 "));
+        assert_eq!(image.lines().count(), 3 + 4);
+    }
 }
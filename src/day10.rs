@@ -1,101 +1,281 @@
 use crate::util::parse_strings;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-fn q1(filename: &str) -> Result<usize> {
-    let codes = parse_strings(filename)?;
-    let mut score = 0;
-    for code in codes {
-        let mut stack = Vec::new();
-        for c in code.chars() {
-            match c {
-                '(' | '{' | '[' | '<' => stack.push(c),
-                ')' => {
-                    if stack.pop().unwrap() != '(' {
-                        score += 3;
-                        break;
-                    }
-                }
-                ']' => {
-                    if stack.pop().unwrap() != '[' {
-                        score += 57;
-                        break;
-                    }
-                }
-                '}' => {
-                    if stack.pop().unwrap() != '{' {
-                        score += 1197;
-                        break;
-                    }
-                }
-                '>' => {
-                    if stack.pop().unwrap() != '<' {
-                        score += 25137;
-                        break;
-                    }
-                }
-                _ => panic!("unexpected character"),
-            }
+/// A pair of delimiters and what they are worth when a line goes wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pair {
+    open: char,
+    close: char,
+    /// Score for finding `close` where it does not belong
+    corrupt_score: usize,
+    /// Score for every missing `close` when completing a line
+    complete_score: usize,
+}
+
+/// The delimiters a checker understands. Delimiters between two `quotes` are just text and
+/// inside quotes `escape` makes the next character text as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Syntax {
+    pairs: Vec<Pair>,
+    quotes: Vec<char>,
+    escape: Option<char>,
+    /// Whether characters that are not delimiters or quotes can appear outside quotes
+    allow_text: bool,
+    /// Score for a character the syntax does not allow
+    unexpected_score: usize,
+    /// Completion scores are summed in this base, most recently opened first
+    complete_base: usize,
+}
+
+impl Syntax {
+    /// The navigation subsystem from the puzzle
+    fn puzzle() -> Self {
+        let pair = |open, close, corrupt_score, complete_score| Pair {
+            open,
+            close,
+            corrupt_score,
+            complete_score,
+        };
+        Self {
+            pairs: vec![
+                pair('(', ')', 3, 1),
+                pair('[', ']', 57, 2),
+                pair('{', '}', 1197, 3),
+                pair('<', '>', 25137, 4),
+            ],
+            quotes: vec![],
+            escape: None,
+            allow_text: false,
+            unexpected_score: 0,
+            complete_base: 5,
         }
     }
-    Ok(score)
-}
 
-fn q2(filename: &str) -> Result<usize> {
-    let codes = parse_strings(filename)?;
-    let mut scores = Vec::new();
-    for code in codes {
-        let mut stack = Vec::new();
-        let mut corrupted = false;
-        for c in code.chars() {
-            match c {
-                '(' | '{' | '[' | '<' => stack.push(c),
-                ')' => {
-                    if stack.pop().unwrap() != '(' {
-                        corrupted = true;
-                        break;
-                    }
-                }
-                ']' => {
-                    if stack.pop().unwrap() != '[' {
-                        corrupted = true;
-                        break;
-                    }
-                }
-                '}' => {
-                    if stack.pop().unwrap() != '{' {
-                        corrupted = true;
-                        break;
-                    }
+    fn opening(&self, c: char) -> Option<&Pair> {
+        self.pairs.iter().find(|p| p.open == c)
+    }
+
+    fn closing(&self, c: char) -> Option<&Pair> {
+        self.pairs.iter().find(|p| p.close == c)
+    }
+
+    /// Walk the line keeping a stack of the pairs still open
+    fn check(&self, line: &str) -> Status {
+        let mut stack: Vec<&Pair> = Vec::new();
+        let mut quote = None;
+        let mut escaped = false;
+        for (position, c) in line.chars().enumerate() {
+            if let Some(q) = quote {
+                if escaped {
+                    escaped = false;
+                } else if Some(c) == self.escape {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
                 }
-                '>' => {
-                    if stack.pop().unwrap() != '<' {
-                        corrupted = true;
-                        break;
+            } else if self.quotes.contains(&c) {
+                quote = Some(c);
+            } else if let Some(pair) = self.opening(c) {
+                stack.push(pair);
+            } else if let Some(pair) = self.closing(c) {
+                match stack.pop() {
+                    Some(open) if open == pair => {}
+                    open => {
+                        return Status::Corrupted {
+                            position,
+                            expected: open.map(|p| p.close),
+                            found: c,
+                        }
                     }
                 }
-                _ => panic!("unexpected character"),
+            } else if !self.allow_text {
+                return Status::Unexpected { position, found: c };
             }
         }
-        if !corrupted {
-            let mut score = 0;
-            for brace in stack.iter().rev() {
-                score *= 5;
-                match brace {
-                    '(' => score += 1,
-                    '[' => score += 2,
-                    '{' => score += 3,
-                    '<' => score += 4,
-                    _ => panic!("unexpected"),
+        let mut missing = String::new();
+        if let Some(q) = quote {
+            // A line ending on the escape needs the escaped character before the quote can close
+            if escaped {
+                missing.push(q);
+            }
+            missing.push(q);
+        }
+        missing.extend(stack.iter().rev().map(|p| p.close));
+        if missing.is_empty() {
+            Status::Valid
+        } else {
+            Status::Incomplete { missing }
+        }
+    }
+
+    /// The smallest change that leaves `line` valid: at most one substitution or deletion at or
+    /// before the first corruption, then the closers it still needs appended. Of the edits that
+    /// remove the corruption the one needing the shortest completion wins, then the one closest
+    /// to the corruption with deletions before substitutions. `None` when one edit is not enough.
+    fn repair(&self, line: &str) -> Option<Repair> {
+        let chars: Vec<char> = line.chars().collect();
+        let corrupted_at = match self.check(line) {
+            Status::Valid => return Some(Repair::default()),
+            Status::Incomplete { missing } => {
+                return Some(Repair {
+                    edit: None,
+                    completion: missing,
+                })
+            }
+            Status::Corrupted { position, .. } | Status::Unexpected { position, .. } => position,
+        };
+        let replacements: Vec<char> = self
+            .pairs
+            .iter()
+            .flat_map(|p| [p.open, p.close])
+            .chain(self.quotes.iter().cloned())
+            .collect();
+        let mut best: Option<Repair> = None;
+        for position in (0..=corrupted_at).rev() {
+            let edits = std::iter::once(Edit::Delete { position }).chain(
+                replacements
+                    .iter()
+                    .filter(|c| **c != chars[position])
+                    .map(|c| Edit::Substitute { position, with: *c }),
+            );
+            for edit in edits {
+                let edited = edit.apply(&chars);
+                let completion = match self.check(&edited) {
+                    Status::Valid => String::new(),
+                    Status::Incomplete { missing } => missing,
+                    Status::Corrupted { .. } | Status::Unexpected { .. } => continue,
+                };
+                if best
+                    .as_ref()
+                    .is_none_or(|b| completion.len() < b.completion.len())
+                {
+                    best = Some(Repair {
+                        edit: Some(edit),
+                        completion,
+                    });
                 }
             }
-            scores.push(score);
         }
+        best
+    }
+
+    /// The syntax error score of a line. A closer with nothing open scores like any other
+    /// misplaced closer, a character outside the syntax scores `unexpected_score`.
+    fn error_score(&self, status: &Status) -> usize {
+        match status {
+            Status::Corrupted { found, .. } => self.closing(*found).map_or(0, |p| p.corrupt_score),
+            Status::Unexpected { .. } => self.unexpected_score,
+            Status::Valid | Status::Incomplete { .. } => 0,
+        }
+    }
+
+    fn complete_score(&self, missing: &str) -> usize {
+        missing.chars().fold(0, |score, c| {
+            score * self.complete_base + self.closing(c).map_or(0, |p| p.complete_score)
+        })
     }
-    scores.sort();
-    //dbg!(&scores);
+}
+
+/// What is wrong with a line, if anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Status {
+    Valid,
+    /// `found` closes a pair that is not the innermost open one, `expected` is the closer
+    /// that was due or `None` when nothing was open
+    Corrupted {
+        position: usize,
+        expected: Option<char>,
+        found: char,
+    },
+    /// `found` is not a delimiter and the syntax does not allow plain text
+    Unexpected {
+        position: usize,
+        found: char,
+    },
+    /// The line ran out with pairs (or a quote) still open, `missing` closes them in order.
+    /// A quote left open on the escape character is escaped by a copy of the quote first.
+    Incomplete {
+        missing: String,
+    },
+}
+
+/// A single character change, positions count characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Substitute { position: usize, with: char },
+    Delete { position: usize },
+}
+
+impl Edit {
+    fn apply(&self, chars: &[char]) -> String {
+        match *self {
+            Edit::Substitute { position, with } => chars
+                .iter()
+                .enumerate()
+                .map(|(i, c)| if i == position { with } else { *c })
+                .collect(),
+            Edit::Delete { position } => chars
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != position)
+                .map(|(_, c)| c)
+                .collect(),
+        }
+    }
+}
+
+/// How to fix a line: an optional edit for a corruption and the closers to append
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Repair {
+    edit: Option<Edit>,
+    completion: String,
+}
+
+impl Repair {
+    /// The repaired line
+    fn apply(&self, line: &str) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut fixed = match self.edit {
+            Some(edit) => edit.apply(&chars),
+            None => line.to_string(),
+        };
+        fixed += &self.completion;
+        fixed
+    }
+}
+
+/// Sum of the error scores of every line
+fn error_total(syntax: &Syntax, codes: &[String]) -> usize {
+    codes
+        .iter()
+        .map(|code| syntax.error_score(&syntax.check(code)))
+        .sum()
+}
+
+/// Middle completion score of the incomplete lines, an error when none are incomplete
+fn completion_median(syntax: &Syntax, codes: &[String]) -> Result<usize> {
+    let mut scores: Vec<usize> = codes
+        .iter()
+        .filter_map(|code| match syntax.check(code) {
+            Status::Incomplete { missing } => Some(syntax.complete_score(&missing)),
+            _ => None,
+        })
+        .collect();
+    if scores.is_empty() {
+        bail!("no incomplete lines to score");
+    }
+    scores.sort_unstable();
     Ok(scores[scores.len() / 2])
 }
 
+fn q1(filename: &str) -> Result<usize> {
+    Ok(error_total(&Syntax::puzzle(), &parse_strings(filename)?))
+}
+
+fn q2(filename: &str) -> Result<usize> {
+    completion_median(&Syntax::puzzle(), &parse_strings(filename)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,4 +284,138 @@ mod tests {
         assert_eq!(q1("./data/day10.txt").unwrap(), 319329);
         assert_eq!(q2("./data/day10.txt").unwrap(), 3515583998);
     }
+
+    #[test]
+    fn test_repair() {
+        let syntax = Syntax::puzzle();
+        let codes = parse_strings("./data/day10.txt").unwrap();
+        for code in &codes {
+            let repair = syntax.repair(code).unwrap();
+            assert_eq!(syntax.check(&repair.apply(code)), Status::Valid);
+        }
+
+        // Swapping the stray closer is enough, the line is then complete
+        let repair = syntax.repair("(]").unwrap();
+        assert_eq!(
+            repair.edit,
+            Some(Edit::Substitute {
+                position: 1,
+                with: ')'
+            })
+        );
+        assert_eq!(repair.completion, "");
+        // Dropping the extra closer beats rewriting the one before it
+        let repair = syntax.repair("<([]>)>").unwrap();
+        assert_eq!(repair.edit, Some(Edit::Delete { position: 4 }));
+        assert_eq!(repair.apply("<([]>)>"), "<([])>");
+    }
+
+    #[test]
+    fn test_malformed() {
+        let syntax = Syntax {
+            unexpected_score: 100,
+            ..Syntax::puzzle()
+        };
+        let closer_first = syntax.check(")()");
+        assert_eq!(
+            closer_first,
+            Status::Corrupted {
+                position: 0,
+                expected: None,
+                found: ')'
+            }
+        );
+        assert_eq!(syntax.error_score(&closer_first), 3);
+        let stray = syntax.check("(a)");
+        assert_eq!(
+            stray,
+            Status::Unexpected {
+                position: 1,
+                found: 'a'
+            }
+        );
+        assert_eq!(syntax.error_score(&stray), 100);
+        assert_eq!(
+            syntax.repair("(a)").unwrap().edit,
+            Some(Edit::Delete { position: 1 })
+        );
+
+        let syntax = Syntax::puzzle();
+        let codes: Vec<String> = [")(", "[x]", "<{"].iter().map(|c| c.to_string()).collect();
+        assert_eq!(error_total(&syntax, &codes), 3);
+        assert_eq!(completion_median(&syntax, &codes).unwrap(), 3 * 5 + 4);
+        assert!(completion_median(&syntax, &["()".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_syntax() {
+        let syntax = Syntax::puzzle();
+        assert_eq!(syntax.check("([]{<>})"), Status::Valid);
+        assert_eq!(
+            syntax.check("{([(<{}[<>[]}>{[]{[(<()>"),
+            Status::Corrupted {
+                position: 12,
+                expected: Some(']'),
+                found: '}'
+            }
+        );
+        let missing = "}}]])})]";
+        assert_eq!(
+            syntax.check("[({(<(())[]>[[{[]{<()<>>"),
+            Status::Incomplete {
+                missing: missing.to_string()
+            }
+        );
+        assert_eq!(syntax.complete_score(missing), 288957);
+        assert_eq!(
+            syntax
+                .repair("[({(<(())[]>[[{[]{<()<>>")
+                .unwrap()
+                .completion,
+            missing
+        );
+
+        let json = Syntax {
+            pairs: vec![
+                Pair {
+                    open: '{',
+                    close: '}',
+                    corrupt_score: 1,
+                    complete_score: 1,
+                },
+                Pair {
+                    open: '[',
+                    close: ']',
+                    corrupt_score: 2,
+                    complete_score: 2,
+                },
+            ],
+            quotes: vec!['"'],
+            escape: Some('\\'),
+            allow_text: true,
+            unexpected_score: 0,
+            complete_base: 3,
+        };
+        assert_eq!(json.check(r#"{"a]\"}":[{}]}"#), Status::Valid);
+        assert_eq!(
+            json.repair(r#"{"a\"":[{]}"#).unwrap(),
+            Repair {
+                edit: Some(Edit::Delete { position: 8 }),
+                completion: String::new(),
+            }
+        );
+        assert_eq!(
+            json.check(r#"[{"x"#),
+            Status::Incomplete {
+                missing: "\"}]".to_string()
+            }
+        );
+        // The completion has to fill in the escaped character before closing the quote
+        let completion = json.repair(r#"["ab\"#).unwrap().completion;
+        assert_eq!(completion, "\"\"]");
+        assert_eq!(
+            json.check(&format!(r#"["ab\{}"#, completion)),
+            Status::Valid
+        );
+    }
 }